@@ -0,0 +1,135 @@
+use crate::functions::update::Step;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub type Result<T> = std::result::Result<T, PackpalError>;
+
+/// packpal 所有操作可能遇到的错误，每种错误都带着出问题的文件路径
+#[derive(Debug)]
+pub enum PackpalError {
+    /// 读写文件、创建目录失败
+    Io { path: PathBuf, source: io::Error },
+    /// config.json 不存在或者格式错误
+    Config { path: PathBuf, message: String },
+    /// metadata.json 等文章元数据格式错误
+    Metadata { path: PathBuf, message: String },
+    /// 模板文件格式错误
+    Template { path: PathBuf, message: String },
+    /// 渲染文章或页面失败
+    Render { path: PathBuf, message: String },
+    /// 执行git部署失败
+    Deploy { remote: String, message: String },
+    /// packpal update 中的某一步失败
+    Update {
+        step: Step,
+        source: Box<PackpalError>,
+    },
+    /// packpal serve 启动预览服务器或监听文件变化失败
+    Serve { message: String },
+    /// 命令行参数不足或者不适用于这个路径，例如clean --all没有加--yes
+    Usage { path: PathBuf, message: String },
+}
+
+impl PackpalError {
+    pub fn io(path: impl AsRef<Path>, source: io::Error) -> Self {
+        PackpalError::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn config(path: impl AsRef<Path>, message: impl ToString) -> Self {
+        PackpalError::Config {
+            path: path.as_ref().to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn metadata(path: impl AsRef<Path>, message: impl ToString) -> Self {
+        PackpalError::Metadata {
+            path: path.as_ref().to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn template(path: impl AsRef<Path>, message: impl ToString) -> Self {
+        PackpalError::Template {
+            path: path.as_ref().to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn render(path: impl AsRef<Path>, message: impl ToString) -> Self {
+        PackpalError::Render {
+            path: path.as_ref().to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn deploy(remote: impl ToString, message: impl ToString) -> Self {
+        PackpalError::Deploy {
+            remote: remote.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn serve(message: impl ToString) -> Self {
+        PackpalError::Serve {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn usage(path: impl AsRef<Path>, message: impl ToString) -> Self {
+        PackpalError::Usage {
+            path: path.as_ref().to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for PackpalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackpalError::Io { path, source } => {
+                write!(f, "[错误]读写{}失败：{source}", path.display())
+            }
+            PackpalError::Config { path, message } => {
+                write!(f, "[错误]配置文件{}有误：{message}", path.display())
+            }
+            PackpalError::Metadata { path, message } => {
+                write!(f, "[错误]文章元数据{}有误：{message}", path.display())
+            }
+            PackpalError::Template { path, message } => {
+                write!(f, "[错误]模板{}有误：{message}", path.display())
+            }
+            PackpalError::Render { path, message } => {
+                write!(f, "[错误]渲染{}失败：{message}", path.display())
+            }
+            PackpalError::Deploy { remote, message } => {
+                if remote.is_empty() {
+                    write!(f, "[错误]部署失败：{message}")
+                } else {
+                    write!(f, "[错误]部署到{remote}失败：{message}")
+                }
+            }
+            PackpalError::Update { step, source } => {
+                write!(f, "[错误]update在{step}步骤失败\n{source}")
+            }
+            PackpalError::Serve { message } => write!(f, "[错误]预览服务器出错：{message}"),
+            PackpalError::Usage { path, message } => {
+                write!(f, "[错误]没有处理{}：{message}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for PackpalError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PackpalError::Io { source, .. } => Some(source),
+            PackpalError::Update { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
@@ -6,5 +6,8 @@ static BLOG: &str = include_str!("./templates/比特币.md"); // 用于测试的
 
 pub mod build;
 pub mod clean;
+pub mod config;
 pub mod deploy;
 pub mod new;
+pub mod serve;
+pub mod update;
@@ -1,179 +1,691 @@
-use crate::functions::build::index::{IndexInfo, IndexTemplate};
+use crate::error::{PackpalError, Result};
+use crate::functions::build::archives::{
+    ARCHIVES_OUTPUT, ArchiveTemplate, archive_posts, collect_archives,
+};
+use crate::functions::build::assets::{Asset, copy_assets};
+use crate::functions::build::cache::{BuildCache, CACHE_FILE, ContentHash};
+use crate::functions::build::feed::{Feed, FeedEntry};
+use crate::functions::build::highlight::HIGHLIGHT_CSS;
+use crate::functions::build::images::{
+    check_collisions, generate, image_size, responsive, variant_cache_dir,
+};
+use crate::functions::build::index::{
+    IndexInfo, IndexTemplate, PostInfo, page_root, page_url, sort_posts,
+};
+use crate::functions::build::manifest::BuildManifest;
+use crate::functions::build::offline::Vendor;
+use crate::functions::build::sitemap::Sitemap;
+use crate::functions::build::tags::{TAGS_OUTPUT, TagTemplate, collect_tags};
+use crate::functions::clean::remove_generated;
+use crate::functions::config::SiteConfig;
 use chrono::Datelike;
+use rayon::ThreadPoolBuilder;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
-use serde::{Deserialize, Serialize};
 
-mod index;
+mod archives;
+pub mod assets;
+pub mod cache;
+pub mod dates;
+pub mod feed;
+mod front_matter;
+pub mod highlight;
+pub mod images;
+pub mod index;
+pub mod manifest;
+pub mod markdown;
+mod math;
+pub mod offline;
 mod posts;
+pub mod sitemap;
+mod tags;
+mod template;
 
 use crate::functions::build::posts::{PostMetadataList, PostTemplate, RawPost, SourceType};
+use crate::functions::build::template::TemplateEngine;
+pub use crate::functions::build::template::{DEFAULT_PAGES, DEFAULT_PARTIALS};
 
 #[derive(Clone, Debug)]
 pub struct Avatar {
-    data: Vec<u8>, // 头像的二进制数据
-    url: String,   // 相对于网站根目录的url
+    data: Vec<u8>,            // 头像的二进制数据
+    url: String,              // 相对于网站根目录的url
+    source: PathBuf,          // 头像源文件路径
+    size: Option<(u32, u32)>, // 头像的宽和高
 }
 
 impl Avatar {
-    pub fn imports(path: PathBuf) -> Self {
-        let data = fs::read(&path).expect("[错误]读取头像文件失败，无效路径");
-        Self {
+    pub fn imports(path: PathBuf) -> Result<Self> {
+        let data = fs::read(&path).map_err(|e| PackpalError::io(&path, e))?;
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| PackpalError::config(&path, "头像路径不是一个文件"))?;
+        Ok(Self {
             data,
-            url: format!("public/{}", path.file_name().unwrap().to_string_lossy()),
+            url: format!("public/{}", file_name.to_string_lossy()),
+            size: image_size(&path),
+            source: path,
+        })
+    }
+
+    // 头像和文章中的图片一样生成不同尺寸的版本
+    fn asset(&self) -> Asset {
+        Asset {
+            source: self.source.clone(),
+            target: self.url.clone(),
+            size: self.size,
         }
     }
 
-    pub fn write_into_file(&self, path: PathBuf) {
-        fs::write(&path, &self.data).expect("[错误]写入头像文件失败，无效路径");
+    pub fn write_into_file(&self, path: PathBuf) -> Result<()> {
+        // 头像没有变化时不重写文件
+        if fs::read(&path).is_ok_and(|data| data == self.data) {
+            return Ok(());
+        }
+        fs::write(&path, &self.data).map_err(|e| PackpalError::io(&path, e))
+    }
+}
+
+/// 构建选项
+#[derive(Clone, Debug, Default)]
+pub struct BuildOptions {
+    /// 忽略构建缓存，重新生成所有页面
+    pub force: bool,
+    /// 并行渲染文章的线程数，None表示使用所有CPU核心
+    pub jobs: Option<usize>,
+    /// 以离线模式生成，和config.json中的offline.enabled作用相同
+    pub offline: bool,
+    /// 生成标记为草稿的文章
+    pub drafts: bool,
+    /// 生成发表时间还没到的文章
+    pub future: bool,
+}
+
+impl BuildOptions {
+    // 页面的输入和上一次构建时不同，或者输出文件不见了，就需要重新生成
+    fn is_stale(&self, cached: Option<&str>, fingerprint: &str, target: &Path) -> bool {
+        self.force || cached != Some(fingerprint) || !target.exists()
+    }
+}
+
+// 一篇文章的构建结果
+struct PostOutput {
+    name: String,
+    info: PostInfo,
+    fingerprint: String,
+    target: PathBuf,
+    rendered: bool,          // 是否重新渲染了，输入没有变化时为false
+    assets: Vec<Asset>,      // 文章引用的本地文件
+    content: Option<String>, // 文章正文的HTML，只在订阅源需要全文时保留
+}
+
+/// 站点用到的所有模板
+#[derive(Clone)]
+pub struct SiteTemplates {
+    post: PostTemplate,
+    index: IndexTemplate,
+    tag: TagTemplate,
+    archive: ArchiveTemplate,
+}
+
+impl SiteTemplates {
+    pub fn imports(engine: &TemplateEngine) -> Result<Self> {
+        Ok(SiteTemplates {
+            post: PostTemplate::imports(engine)?,
+            index: IndexTemplate::imports(engine)?,
+            tag: TagTemplate::imports(engine)?,
+            archive: ArchiveTemplate::imports(engine)?,
+        })
     }
 }
 
 #[derive(Clone)]
 pub struct SiteFactory {
-    blog_name: String,
+    config: SiteConfig,
     avatar: Avatar,
-    email: String,
-    github: String,
-    motto: String,
     posts: Vec<RawPost>,
     metadata: PostMetadataList,
-    post_template: PostTemplate,
-    index_template: IndexTemplate,
+    templates: SiteTemplates,
+    vendor: Option<Vendor>, // 离线模式下外部资源的本地副本，不是离线模式时为None
 }
 
 impl SiteFactory {
     pub fn new(
-        blog_name: String,
+        config: &SiteConfig,
         avatar: Avatar,
-        email: String,
-        github: String,
-        motto: String,
         posts: Vec<RawPost>,
         metadata: PostMetadataList,
-        post_template: PostTemplate,
-        index_template: IndexTemplate,
+        templates: SiteTemplates,
+        vendor: Option<Vendor>,
     ) -> Self {
         SiteFactory {
-            blog_name,
+            config: config.clone(),
             avatar,
-            email,
-            github,
-            motto,
             posts,
             metadata,
-            post_template,
-            index_template,
+            templates,
+            vendor,
+        }
+    }
+
+    // 离线模式会改写页面中的外部资源，开关离线模式时所有页面都需要重新生成；
+    // 图片选项决定页面中<img>的srcset，日期格式和时区决定页面中的日期，修改这些选项时也一样
+    fn page_fingerprint(&self, fingerprint: String) -> String {
+        let hash = ContentHash::new()
+            .update(fingerprint)
+            .update(serde_json::to_string(&self.config.images).unwrap_or_default())
+            .update(&self.config.date_format)
+            .update(&self.config.timezone);
+        match self.vendor {
+            Some(_) => hash.update("offline").finish(),
+            None => hash.finish(),
+        }
+    }
+
+    // 订阅源和站点地图中的链接必须是完整地址，没有设置site_url时不生成
+    fn feeds_enabled(&self) -> bool {
+        self.config.feed.enabled && !self.config.site_url.is_empty()
+    }
+
+    // 离线模式下把页面中的外部资源换成本地副本，root是页面到网站根目录的相对路径
+    fn localize(&self, html: &str, root: &str, page: &Path) -> Result<String> {
+        match &self.vendor {
+            Some(vendor) => vendor.localize(html, root, page),
+            None => Ok(html.to_string()),
+        }
+    }
+
+    // 生成标签页、归档页这样的列表页，url是页面所在目录相对于网站根目录的地址，root正相反；
+    // stale判断页面的输入有没有变化，没有变化并且页面还在时不重新渲染
+    fn write_listing(
+        &self,
+        out_dir: &Path,
+        url: &str,
+        root: &str,
+        stale: impl FnOnce(&Path) -> bool,
+        render: impl FnOnce(&Path) -> Result<String>,
+        manifest: &mut BuildManifest,
+    ) -> Result<()> {
+        let dir = out_dir.join(url);
+        let path = dir.join("index.html");
+        if stale(&path) {
+            fs::create_dir_all(&dir).map_err(|e| PackpalError::io(&dir, e))?;
+            let html = self.localize(&render(&path)?, root, &path)?;
+            if fs::read_to_string(&path).ok() != Some(html.clone()) {
+                fs::write(&path, html).map_err(|e| PackpalError::io(&path, e))?;
+            }
+        }
+        manifest.record(out_dir, path);
+        Ok(())
+    }
+
+    // 读取一篇文章，输入有变化时渲染并写入articles_dir；草稿和还没到发表时间的文章默认跳过，返回None
+    fn build_post(
+        &self,
+        mut post: RawPost,
+        articles_dir: &Path,
+        cache: &BuildCache,
+        options: &BuildOptions,
+    ) -> Result<Option<PostOutput>> {
+        post.load()?;
+        if let Some(metadata) = self.metadata.get(&post.name) {
+            post.merge_metadata(metadata);
+        }
+        // 日期无法识别时报错，而不是悄悄地把文章当成没有日期
+        let date = post.date(self.config.timezone())?;
+        if (post.is_draft() && !options.drafts)
+            || (date.is_some_and(|date| date.is_future()) && !options.future)
+        {
+            return Ok(None);
         }
+        let target = articles_dir.join(format!("{}.html", post.name));
+        let fingerprint =
+            self.page_fingerprint(post.fingerprint(&self.templates.post, &self.config.markdown));
+        let name = post.name.clone();
+        let full_content = self.feeds_enabled() && self.config.feed.full_content();
+        // 引用的文件被删除时也重新渲染，由渲染过程报告找不到的文件；
+        // 引用的图片尺寸变化时<img>的宽高也要更新
+        let rendered = options.is_stale(cache.post(&name), &fingerprint, &target)
+            || cache
+                .assets(&name)
+                .iter()
+                .any(|asset| !asset.source.is_file() || image_size(&asset.source) != asset.size)
+            // 订阅源改成放全文时，缓存中还没有正文的文章需要重新渲染
+            || (full_content && cache.content(&name).is_none());
+        let (info, assets, content) = if rendered {
+            let (info, html, assets) = post.render(
+                &self.templates.post,
+                &self.config.markdown,
+                &self.config.date_format,
+                self.config.timezone(),
+            )?;
+            let content = full_content.then(|| html.body().to_string());
+            let html = html
+                .rewrite(|html| self.localize(html, "../", &target))?
+                .rewrite(|html| {
+                    responsive(
+                        html,
+                        "../",
+                        &assets,
+                        &self.config.images,
+                        self.config.images.lazy,
+                        &target,
+                    )
+                })?;
+            html.write_into_folder(articles_dir)?;
+            (info, assets, content)
+        } else {
+            // 没有重新渲染的文章引用的文件和正文都和上一次构建时相同
+            let content = full_content
+                .then(|| cache.content(&name).map(str::to_string))
+                .flatten();
+            (
+                post.info(
+                    &self.config.markdown,
+                    &self.config.date_format,
+                    self.config.timezone(),
+                ),
+                cache.assets(&name).to_vec(),
+                content,
+            )
+        };
+        Ok(Some(PostOutput {
+            name,
+            info,
+            fingerprint,
+            target,
+            rendered,
+            assets,
+            content,
+        }))
     }
 
-    pub fn build(self, out_dir: impl AsRef<Path>) {
-        let out_dir = out_dir.as_ref().display();
+    pub fn build(mut self, out_dir: impl AsRef<Path>, options: &BuildOptions) -> Result<()> {
+        let out_dir = out_dir.as_ref();
         // 在dist_dir下面生成 articles 和 public 文件夹
-        let dist_public_dir = format!("{out_dir}public/");
-        let dist_articles_dir = format!("{out_dir}articles/");
-        fs::create_dir_all(&dist_public_dir).expect("[错误]构建时创建文件夹失败");
-        fs::create_dir_all(&dist_articles_dir).expect("[错误]构建时创建文件夹失败");
-
-        let metadata_list = PostMetadataList::from_json("posts.json");
-        let mut post_info_list = Vec::new();
-        for mut post in self.posts {
-            if let Some(metadata) = self.metadata.get(&post.name) {
-                post.set_date(metadata.date.clone());
-                post.set_tag(metadata.tags.clone());
+        let dist_public_dir = out_dir.join("public");
+        let dist_articles_dir = out_dir.join("articles");
+        fs::create_dir_all(&dist_public_dir).map_err(|e| PackpalError::io(&dist_public_dir, e))?;
+        fs::create_dir_all(&dist_articles_dir)
+            .map_err(|e| PackpalError::io(&dist_articles_dir, e))?;
+
+        // 记录这次构建写入的所有文件，供packpal clean使用
+        let mut manifest = BuildManifest::default();
+        // 上一次构建的缓存，以及这一次构建之后的缓存
+        let previous_cache = BuildCache::load(out_dir);
+        let mut cache = BuildCache::default();
+
+        // 文章之间互不影响，在线程池中并行渲染；collect保持文章原来的顺序，出错时返回第一个错误
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(options.jobs.unwrap_or(0))
+            .build()
+            .map_err(|e| PackpalError::render(out_dir, format!("无法创建线程池：{e}")))?;
+        let posts = std::mem::take(&mut self.posts);
+        let total = posts.len();
+        let outputs = pool.install(|| {
+            posts
+                .into_par_iter()
+                .map(|post| self.build_post(post, &dist_articles_dir, &previous_cache, options))
+                .collect::<Result<Vec<_>>>()
+        })?;
+        let outputs = outputs.into_iter().flatten().collect::<Vec<_>>();
+
+        // 文章引用的文件每次都检查一遍，文章没有变化、引用的文件变化了也能更新
+        copy_assets(
+            outputs.iter().flat_map(|output| &output.assets),
+            out_dir,
+            &mut manifest,
+        )?;
+
+        // 为文章中的图片和头像生成不同尺寸的版本，图片和选项都没有变化时沿用上一次生成的文件
+        let avatar = self.avatar.asset();
+        check_collisions(
+            outputs
+                .iter()
+                .flat_map(|output| &output.assets)
+                .chain([&avatar]),
+            &self.config.images,
+        )?;
+        let cache_dir = variant_cache_dir();
+        let images = outputs
+            .iter()
+            .flat_map(|output| &output.assets)
+            .chain([&avatar])
+            .filter(|asset| asset.size.is_some())
+            .map(|asset| (asset.target.clone(), asset.clone()))
+            .collect::<BTreeMap<_, _>>();
+        let variants = pool.install(|| {
+            images
+                .into_par_iter()
+                .map(|(target, image)| {
+                    let data =
+                        fs::read(&image.source).map_err(|e| PackpalError::io(&image.source, e))?;
+                    let fingerprint = self.config.images.fingerprint(&data);
+                    let files = generate(
+                        &image,
+                        &self.config.images,
+                        out_dir,
+                        &cache_dir,
+                        &fingerprint,
+                        previous_cache.image(&target),
+                    )?;
+                    Ok((target, fingerprint, files))
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+        for (target, fingerprint, files) in variants {
+            for file in files {
+                manifest.record(out_dir, out_dir.join(file));
+            }
+            cache.set_image(target, fingerprint);
+        }
+
+        let mut post_info_list = Vec::with_capacity(outputs.len());
+        let mut feed_entries = Vec::with_capacity(outputs.len());
+        let mut rendered = 0;
+        for output in outputs {
+            manifest.record(out_dir, &output.target);
+            cache.set_assets(&output.name, output.assets);
+            if let Some(content) = &output.content {
+                cache.set_content(&output.name, content.clone());
+            }
+            // 草稿只用来预览，不出现在订阅源中
+            if !output.info.draft {
+                feed_entries.push(FeedEntry {
+                    info: output.info.clone(),
+                    content: output.content,
+                });
+            }
+            cache.set_post(output.name, output.fingerprint);
+            rendered += usize::from(output.rendered);
+            post_info_list.push(output.info);
+        }
+        // 主页、标签页和订阅源中的文章都按发表时间从新到旧排列
+        sort_posts(&mut post_info_list);
+        println!(
+            "[信息]生成了{rendered}篇文章，{}篇文章没有变化",
+            post_info_list.len() - rendered
+        );
+        if total > post_info_list.len() {
+            println!(
+                "[信息]跳过了{}篇草稿或者还没到发表时间的文章，使用--drafts、--future可以生成它们",
+                total - post_info_list.len()
+            );
+        }
+
+        // class模式下代码的颜色写在样式表里，主题变化时样式表跟着变化
+        if let Some(css) = self.config.markdown.highlight.stylesheet() {
+            let css_path = dist_public_dir.join(HIGHLIGHT_CSS);
+            if fs::read_to_string(&css_path).ok() != Some(css.clone()) {
+                fs::write(&css_path, css).map_err(|e| PackpalError::io(&css_path, e))?;
+            }
+            manifest.record(out_dir, css_path);
+        }
+
+        if let Some(vendor) = &self.vendor {
+            vendor.copy_into(out_dir, &mut manifest)?;
+        }
+
+        if self.feeds_enabled() {
+            let feed = Feed::new(
+                self.config.blog_name.clone(),
+                self.config.motto.clone(),
+                self.config.blog_name.clone(),
+                self.config.contacts.email.clone(),
+                &self.config.site_url,
+                feed_entries,
+                &self.config.feed,
+            );
+            feed.write_into(out_dir, &mut manifest)?;
+        }
+
+        let avatar_path = out_dir.join(&self.avatar.url);
+        self.avatar.write_into_file(avatar_path.clone())?;
+        manifest.record(out_dir, avatar_path);
+
+        // 主页的最后修改日期是最新一篇文章的日期，--drafts生成的草稿不出现在站点地图中
+        let mut sitemap = Sitemap::new(&self.config.site_url);
+        sitemap.add_listing("", &post_info_list);
+        for post in post_info_list.iter().filter(|post| !post.draft) {
+            sitemap.add(&post.url, [post.datetime]);
+        }
+
+        // 每个标签一个页面（tags/<标签>/），再加上所有标签的总览页（tags/）
+        let tags = collect_tags(&post_info_list);
+        let pages = tags
+            .iter()
+            .map(|tag| (Some(tag), tag.url.clone(), "../../"))
+            .chain([(None, format!("{TAGS_OUTPUT}/"), "../")]);
+        for (tag, url, root) in pages {
+            let fingerprint = self.page_fingerprint(self.templates.tag.fingerprint(
+                &self.config.blog_name,
+                &tags,
+                tag,
+            ));
+            let stale =
+                |path: &Path| options.is_stale(previous_cache.listing(&url), &fingerprint, path);
+            let render = |path: &Path| {
+                self.templates
+                    .tag
+                    .render(&self.config.blog_name, &tags, tag, root, path)
+            };
+            self.write_listing(out_dir, &url, root, stale, render, &mut manifest)?;
+            cache.set_listing(&url, fingerprint);
+            match tag {
+                Some(tag) => sitemap.add_published_listing(&url, &tag.posts),
+                None => sitemap.add_listing(&url, &post_info_list),
             }
-            let (post_info, target) = post.render(&self.post_template);
-            target.write_into_folder(&dist_articles_dir);
-            post_info_list.push(post_info);
         }
 
-        self.avatar
-            .write_into_file(format!("{}{}", out_dir, self.avatar.url).into());
+        // 按年、按月的归档页（archives/2024/、archives/2024/09/），再加上所有文章的时间线（archives/）
+        let years = collect_archives(&post_info_list);
+        let mut pages = vec![(None, None, format!("{ARCHIVES_OUTPUT}/"), "../")];
+        for year in &years {
+            pages.push((Some(year), None, year.url.clone(), "../../"));
+            for month in &year.months {
+                pages.push((Some(year), Some(month), month.url.clone(), "../../../"));
+            }
+        }
+        for (year, month, url, root) in pages {
+            let fingerprint = self.page_fingerprint(self.templates.archive.fingerprint(
+                &self.config.blog_name,
+                &years,
+                year,
+                month,
+            ));
+            let stale =
+                |path: &Path| options.is_stale(previous_cache.listing(&url), &fingerprint, path);
+            let render = |path: &Path| {
+                self.templates.archive.render(
+                    &self.config.blog_name,
+                    &years,
+                    year,
+                    month,
+                    root,
+                    path,
+                )
+            };
+            self.write_listing(out_dir, &url, root, stale, render, &mut manifest)?;
+            cache.set_listing(&url, fingerprint);
+            let posts = archive_posts(&years, year, month);
+            match year {
+                Some(_) => sitemap.add_published_listing(&url, &posts),
+                None => sitemap.add_listing(&url, &posts),
+            }
+        }
 
-        let this_year = chrono::Local::now().year();
+        let this_year = chrono::Utc::now()
+            .with_timezone(&self.config.timezone())
+            .year();
         let index_info = IndexInfo {
-            site_name: self.blog_name.clone(),
-            motto: self.motto.clone(),
+            site_name: self.config.blog_name.clone(),
+            motto: self.config.motto.clone(),
+            github: self.config.contacts.github.clone(),
+            email: self.config.contacts.email.clone(),
+            date: this_year.to_string(),
             avatar: self.avatar.clone(),
-            email: self.email.clone(),
-            github: self.github.clone(),
             posts: post_info_list,
-            date: this_year.to_string(),
+            feed: self.feeds_enabled(),
         };
-        let index = self.index_template.render(index_info);
-        index.write_into_file(format!("{out_dir}index.html").into());
-    }
-}
+        // 文章列表和站点信息都没有变化时，主页不需要重新生成
+        let index_path = out_dir.join("index.html");
+        // 主页按config.json中的page_size分页，第2页开始在page/<页码>/下
+        let total_pages = self.config.index.total_pages(index_info.posts.len());
+        let pages = (1..=total_pages)
+            .map(|page| (page, out_dir.join(page_url(page)).join("index.html")))
+            .collect::<Vec<_>>();
+        // 头像的尺寸写在主页的<img>中
+        let fingerprint = self.page_fingerprint(
+            ContentHash::new()
+                .update(index_info.fingerprint(&self.templates.index))
+                .update(format!("{:?}", self.avatar.size))
+                .update(serde_json::to_string(&self.config.index).unwrap_or_default())
+                .finish(),
+        );
+        if options.is_stale(previous_cache.index(), &fingerprint, &index_path)
+            || pages.iter().any(|(_, path)| !path.exists())
+        {
+            for (page, path) in &pages {
+                let root = page_root(*page);
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| PackpalError::io(dir, e))?;
+                }
+                let index = self
+                    .templates
+                    .index
+                    .render(&index_info, *page, &self.config.index, path)?
+                    .rewrite(|html| self.localize(html, root, path))?
+                    .rewrite(|html| {
+                        responsive(
+                            html,
+                            root,
+                            &[self.avatar.asset()],
+                            &self.config.images,
+                            false,
+                            path,
+                        )
+                    })?;
+                index.write_into_file(path.clone())?;
+            }
+        }
+        cache.set_index(fingerprint);
+        for (page, path) in pages {
+            manifest.record(out_dir, path);
+            if page > 1 {
+                let posts = self.config.index.page(&index_info.posts, page);
+                sitemap.add_published_listing(&page_url(page), posts);
+            }
+        }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct SiteConfig {
-    blog_name: String,
-    #[serde(rename = "avatar")]
-    avatar_path: String,
-    email: String,
-    github: String,
-    motto: String,
-}
+        if self.config.sitemap.enabled && !self.config.site_url.is_empty() {
+            sitemap.write_into(out_dir, &self.config.sitemap, &mut manifest)?;
+        }
+        if self.config.site_url.is_empty()
+            && (self.config.feed.enabled || self.config.sitemap.enabled)
+        {
+            println!("[警告]config.json中没有设置site_url，不会生成订阅源和站点地图");
+        }
 
-impl SiteConfig {
-    fn from_json(path: PathBuf) -> SiteConfig {
-        let config = fs::read_to_string(path).expect("[错误]读取配置文件失败，无效路径");
-        serde_json::from_str(&config).expect("[错误]解析配置文件失败，无效JSON格式")
+        // 上一次构建生成、这一次没有再生成的文件（例如被删除的文章）已经过时了
+        if let Some(previous) = BuildManifest::load(out_dir)? {
+            let stale = previous.files().filter(|file| !manifest.contains(file));
+            remove_generated(out_dir, stale)?;
+        }
+        cache.save(out_dir)?;
+        manifest.record(out_dir, CACHE_FILE);
+        manifest.save(out_dir)
     }
 }
 
 /// 扫秒指定文件夹source_dir下的源文件（e.g. markdown文件），生成静态站点文件到dist_dir内
 pub fn build(
+    config_path: impl AsRef<Path>,
     source_dir: impl AsRef<Path>,
     template_dir: impl AsRef<Path>,
     dist_dir: impl AsRef<Path>,
-) {
-    let raw_posts = scan_source_file(&source_dir);
-    let metadata = PostMetadataList::from_json(format!("{}/metadata.json", source_dir.as_ref().display()));
-    let config = SiteConfig::from_json("config.json".into());
-    
+) -> Result<()> {
+    build_with(
+        config_path,
+        source_dir,
+        template_dir,
+        dist_dir,
+        &BuildOptions::default(),
+    )
+}
+
+/// 和build相同，可以通过options控制构建过程
+pub fn build_with(
+    config_path: impl AsRef<Path>,
+    source_dir: impl AsRef<Path>,
+    template_dir: impl AsRef<Path>,
+    dist_dir: impl AsRef<Path>,
+    options: &BuildOptions,
+) -> Result<()> {
+    let raw_posts = scan_source_file(&source_dir)?;
+    // 元数据也可以全部写在front matter里，这时metadata.json可以省略
+    let metadata_path = source_dir.as_ref().join("metadata.json");
+    let metadata = if metadata_path.exists() {
+        PostMetadataList::from_json(metadata_path)?
+    } else {
+        PostMetadataList::default()
+    };
+    let config = SiteConfig::from_json(&config_path)?;
+    let engine = TemplateEngine::load(template_dir, config.timezone())?;
+    let vendor = (options.offline || config.offline.enabled).then(|| {
+        Vendor::new(SiteConfig::resolve(
+            &config_path,
+            &config.offline.vendor_dir,
+        ))
+    });
+
     let factory = SiteFactory::new(
-        config.blog_name,
-        Avatar::imports(config.avatar_path.into()),
-        config.email,
-        config.github,
-        config.motto,
+        &config,
+        Avatar::imports(SiteConfig::resolve(&config_path, &config.avatar_path))?,
         raw_posts,
         metadata,
-        PostTemplate::imports(format!("{}/post.html", template_dir.as_ref().display())),
-        IndexTemplate::imports(format!("{}/index.html", template_dir.as_ref().display())),
+        SiteTemplates::imports(&engine)?,
+        vendor,
     );
-    factory.build(dist_dir);
+    factory.build(dist_dir, options)
 }
 
 // 扫描posts文件夹下所有markdown文件，并返回其元数据
-fn scan_source_file(dir: impl AsRef<Path>) -> Vec<RawPost> {
+fn scan_source_file(dir: impl AsRef<Path>) -> Result<Vec<RawPost>> {
+    let dir = dir.as_ref();
     let mut posts = Vec::new();
-    for entry in fs::read_dir(&dir)
-        .unwrap_or_else(|_| panic!("[错误]读取目录{}失败", dir.as_ref().display()))
+    for entry in fs::read_dir(dir)
+        .map_err(|e| PackpalError::io(dir, e))?
         .flatten()
     {
-        let name = entry
-            .file_name()
-            .into_string()
-            .expect("[错误]读取文件名时遇到无效UTF-8字符");
-        let name = name.split('.').collect::<Vec<&str>>()[0];
-        let name = name.to_string();
-
         let path = entry.path();
-
-        let source_type = match path
-            .extension()
-            .map(|s| s.to_str().expect("[错误]读取文件名扩展时遇到无效UTF-8字符"))
-        {
+        let source_type = match path.extension().and_then(|s| s.to_str()) {
             Some("md") => SourceType::Markdown,
             _ => continue,
         };
 
+        let name = entry
+            .file_name()
+            .into_string()
+            .map(|name| post_name(&name))
+            .map_err(|_| {
+                PackpalError::io(
+                    &path,
+                    io::Error::new(io::ErrorKind::InvalidData, "文件名中有无效的UTF-8字符"),
+                )
+            })?;
         posts.push(RawPost::new(name, source_type, path))
     }
 
-    posts
+    // read_dir返回的顺序和文件系统有关，按文件名排序保证每次构建的结果相同
+    posts.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(posts)
+}
+
+// 源文件名对应的文章名，即第一个`.`之前的部分
+fn post_name(file_name: &str) -> String {
+    file_name.split('.').next().unwrap_or_default().to_string()
 }
 
 #[cfg(test)]
@@ -184,7 +696,7 @@ mod tests {
 
     fn new_test_project(project_name: &str) -> String {
         let test_project_root = format!("{TEST_ROOT}{project_name}");
-        new_project(&test_project_root);
+        new_project(&test_project_root).unwrap();
         fs::write(format!("{}/posts/比特币.md", &test_project_root), BLOG)
             .expect("尝试创建测试博客失败");
         test_project_root
@@ -193,7 +705,7 @@ mod tests {
     #[test]
     fn test_scan_folder() {
         let test_prj_root = new_test_project("test_scan_folder");
-        let posts = scan_source_file(format!("{test_prj_root}/posts/"));
+        let posts = scan_source_file(format!("{test_prj_root}/posts/")).unwrap();
         println!("{:?}", posts);
     }
 
@@ -201,9 +713,233 @@ mod tests {
     fn test_build() {
         let test_prj_root = new_test_project("test_build");
         build(
+            format!("{test_prj_root}/config.json"),
             format!("{test_prj_root}/posts/"),
             format!("{test_prj_root}/templates/"),
             format!("{test_prj_root}/build/"),
+        )
+        .unwrap();
+
+        let out_dir = format!("{test_prj_root}/build/");
+        let manifest = BuildManifest::load(&out_dir).unwrap().unwrap();
+        assert!(manifest.contains("index.html"));
+        assert!(manifest.contains("articles/比特币.html"));
+        assert!(manifest.contains("public/avatar.png"));
+    }
+
+    #[test]
+    fn test_offline_build_new_project() {
+        // packpal new创建的项目不需要准备vendor/就可以离线生成
+        let root = format!("{TEST_ROOT}test_offline_build_new_project");
+        new_project(&root).unwrap();
+        build_with(
+            format!("{root}/config.json"),
+            format!("{root}/posts/"),
+            format!("{root}/templates/"),
+            format!("{root}/build/"),
+            &BuildOptions {
+                offline: true,
+                ..BuildOptions::default()
+            },
+        )
+        .unwrap();
+        for page in [
+            "index.html",
+            "articles/我的第一篇博客.html",
+            "tags/index.html",
+            "archives/index.html",
+        ] {
+            let html = fs::read_to_string(format!("{root}/build/{page}")).unwrap();
+            assert!(!html.contains("https://"), "{page}");
+        }
+    }
+
+    #[test]
+    fn test_incremental_build() {
+        let root = new_test_project("test_incremental_build");
+        let build_project = |force| {
+            build_with(
+                format!("{root}/config.json"),
+                format!("{root}/posts/"),
+                format!("{root}/templates/"),
+                format!("{root}/build/"),
+                &BuildOptions {
+                    force,
+                    ..BuildOptions::default()
+                },
+            )
+            .unwrap()
+        };
+        let read = |file: &str| fs::read_to_string(format!("{root}/build/{file}")).unwrap();
+        let mark = |file: &str| fs::write(format!("{root}/build/{file}"), "旧的输出").unwrap();
+        build_project(false);
+        assert!(
+            BuildManifest::load(format!("{root}/build/"))
+                .unwrap()
+                .unwrap()
+                .contains(CACHE_FILE)
         );
+
+        // 输入没有变化时，文章、主页、标签页和归档页都不会重新生成
+        let listings = ["tags/index.html", "archives/index.html"];
+        for file in ["articles/比特币.html", "index.html"]
+            .iter()
+            .chain(&listings)
+        {
+            mark(file);
+        }
+        build_project(false);
+        assert_eq!(read("articles/比特币.html"), "旧的输出");
+        assert_eq!(read("index.html"), "旧的输出");
+        for file in listings {
+            assert_eq!(read(file), "旧的输出", "{file}");
+        }
+
+        // 修改正文只重新生成这篇文章，主页上的摘要也变了，所以主页也要重新生成
+        fs::write(format!("{root}/posts/比特币.md"), "# 比特币\n新的正文").unwrap();
+        mark("articles/我的第一篇博客.html");
+        build_project(false);
+        assert!(read("articles/比特币.html").contains("新的正文"));
+        assert_eq!(read("articles/我的第一篇博客.html"), "旧的输出");
+        assert_ne!(read("index.html"), "旧的输出");
+        // 比特币没有日期和标签，不在标签页和归档页中
+        for file in listings {
+            assert_eq!(read(file), "旧的输出", "{file}");
+        }
+
+        // 标签总览只有标签名和文章数，没有变化；标签页和归档页中列出了摘要，都要重新生成
+        fs::write(format!("{root}/posts/我的第一篇博客.md"), "新的摘要").unwrap();
+        mark("tags/博客/index.html");
+        mark("archives/2024/09/index.html");
+        build_project(false);
+        assert_eq!(read("tags/index.html"), "旧的输出");
+        assert_ne!(read("tags/博客/index.html"), "旧的输出");
+        assert_ne!(read("archives/index.html"), "旧的输出");
+        assert_ne!(read("archives/2024/09/index.html"), "旧的输出");
+
+        // 模板变化时所有文章都要重新生成，--force 忽略缓存
+        let template = format!("{root}/templates/partials/post_tags.html");
+        fs::write(&template, "标签").unwrap();
+        mark("articles/我的第一篇博客.html");
+        build_project(false);
+        assert_ne!(read("articles/我的第一篇博客.html"), "旧的输出");
+        mark("index.html");
+        build_project(true);
+        assert_ne!(read("index.html"), "旧的输出");
+    }
+
+    #[test]
+    fn test_parallel_build() {
+        let root = new_test_project("test_parallel_build");
+        for i in 0..20 {
+            fs::write(format!("{root}/posts/文章{i:02}.md"), format!("# 文章{i}")).unwrap();
+        }
+        let build_with_jobs = |jobs| {
+            let options = BuildOptions {
+                force: true,
+                jobs: Some(jobs),
+                ..BuildOptions::default()
+            };
+            build_with(
+                format!("{root}/config.json"),
+                format!("{root}/posts/"),
+                format!("{root}/templates/"),
+                format!("{root}/build/"),
+                &options,
+            )
+            .unwrap();
+            fs::read_to_string(format!("{root}/build/index.html")).unwrap()
+        };
+
+        // 不管用几个线程，文章的顺序和生成的页面都一样
+        let sequential = build_with_jobs(1);
+        assert_eq!(sequential, build_with_jobs(4));
+        let first = sequential.find("文章00").unwrap();
+        assert!(first < sequential.find("文章01").unwrap());
+        assert!(fs::exists(format!("{root}/build/articles/文章19.html")).unwrap());
+    }
+
+    #[test]
+    fn test_drafts_and_future_posts() {
+        let root = new_test_project("test_drafts_and_future_posts");
+        let config_path = format!("{root}/config.json");
+        let config = fs::read_to_string(&config_path).unwrap();
+        let config = config.replace(
+            "\"site_url\": \"\"",
+            "\"site_url\": \"https://example.com\"",
+        );
+        fs::write(&config_path, config).unwrap();
+        fs::write(
+            format!("{root}/posts/草稿.md"),
+            "---\ndraft: true\ndate: 2030-01-01\ntags: [未发表]\n---\n# 草稿",
+        )
+        .unwrap();
+        fs::write(
+            format!("{root}/posts/定时发布.md"),
+            "---\ndate: 2999-01-01 08:00\n---\n# 定时发布",
+        )
+        .unwrap();
+        let build_project = |drafts, future| {
+            let options = BuildOptions {
+                drafts,
+                future,
+                ..BuildOptions::default()
+            };
+            build_with(
+                format!("{root}/config.json"),
+                format!("{root}/posts/"),
+                format!("{root}/templates/"),
+                format!("{root}/build/"),
+                &options,
+            )
+            .unwrap();
+        };
+        let exists = |file: &str| fs::exists(format!("{root}/build/{file}")).unwrap();
+
+        build_project(false, false);
+        assert!(exists("articles/比特币.html"));
+        assert!(!exists("articles/草稿.html"));
+        assert!(!exists("articles/定时发布.html"));
+
+        // 草稿在页面中有“草稿”标记
+        build_project(true, true);
+        let draft = fs::read_to_string(format!("{root}/build/articles/草稿.html")).unwrap();
+        assert!(draft.contains("class=\"draft-badge\""));
+        assert!(exists("articles/定时发布.html"));
+
+        // 即使用--drafts生成了草稿，订阅源和站点地图中也不能有草稿
+        let read = |file: &str| fs::read_to_string(format!("{root}/build/{file}")).unwrap();
+        for feed in ["feed.xml", "atom.xml"] {
+            assert!(read(feed).contains("比特币"), "{feed}");
+            assert!(!read(feed).contains("草稿"), "{feed}");
+        }
+        let sitemap = read("sitemap.xml");
+        assert!(!sitemap.contains("%E8%8D%89%E7%A8%BF") && !sitemap.contains("草稿"));
+        assert!(!sitemap.contains("%E6%9C%AA%E5%8F%91%E8%A1%A8") && !sitemap.contains("未发表"));
+        assert!(!sitemap.contains("2030"));
+
+        // 不再使用--drafts、--future时，上一次生成的草稿会被删除
+        build_project(false, false);
+        assert!(!exists("articles/草稿.html"));
+        assert!(!exists("articles/定时发布.html"));
+    }
+
+    #[test]
+    fn test_build_errors_carry_path() {
+        let test_prj_root = new_test_project("test_build_errors");
+        let config_path = format!("{test_prj_root}/config.json");
+        fs::write(&config_path, "{ not json").unwrap();
+
+        let err = build(
+            &config_path,
+            format!("{test_prj_root}/posts/"),
+            format!("{test_prj_root}/templates/"),
+            format!("{test_prj_root}/build/"),
+        )
+        .unwrap_err();
+        match err {
+            PackpalError::Config { path, .. } => assert_eq!(path, PathBuf::from(&config_path)),
+            other => panic!("应该是配置文件错误，实际是{other:?}"),
+        }
     }
 }
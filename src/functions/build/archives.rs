@@ -0,0 +1,197 @@
+use crate::error::Result;
+use crate::functions::build::cache::ContentHash;
+use crate::functions::build::index::PostInfo;
+use crate::functions::build::template::TemplateEngine;
+use chrono::Datelike;
+use minijinja::context;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::path::Path;
+
+/// 归档页模板的文件名
+pub static ARCHIVE_TEMPLATE: &str = "archive_template.html";
+/// 归档页在输出目录中的位置
+pub static ARCHIVES_OUTPUT: &str = "archives";
+
+/// 一年中发表的文章
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveYear {
+    pub year: i32,
+    pub url: String, // 年度归档页相对于网站根目录的地址，例如`archives/2024/`
+    pub count: usize,
+    pub months: Vec<ArchiveMonth>,
+}
+
+/// 一个月中发表的文章
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveMonth {
+    pub year: i32,
+    pub month: u32,
+    pub url: String, // 月度归档页相对于网站根目录的地址，例如`archives/2024/09/`
+    pub count: usize,
+    pub posts: Vec<PostInfo>,
+}
+
+impl ArchiveYear {
+    /// 这一年的所有文章，从新到旧
+    pub fn posts(&self) -> Vec<PostInfo> {
+        self.months
+            .iter()
+            .flat_map(|month| month.posts.iter().cloned())
+            .collect()
+    }
+}
+
+/// 按发表的年份和月份把文章分组，年份、月份和文章都从新到旧排列
+///
+/// 没有日期的文章不会出现在归档中。
+pub fn collect_archives(posts: &[PostInfo]) -> Vec<ArchiveYear> {
+    let mut dated = posts
+        .iter()
+        .filter_map(|post| post.datetime.map(|date| (date.datetime(), post)))
+        .collect::<Vec<_>>();
+    dated.sort_by_key(|(date, _)| Reverse(*date));
+
+    let mut years: Vec<ArchiveYear> = Vec::new();
+    for (date, post) in dated {
+        let (year, month) = (date.year(), date.month());
+        if years.last().is_none_or(|last| last.year != year) {
+            years.push(ArchiveYear {
+                year,
+                url: format!("{ARCHIVES_OUTPUT}/{year}/"),
+                count: 0,
+                months: Vec::new(),
+            });
+        }
+        let archive = years.last_mut().unwrap();
+        archive.count += 1;
+        if archive.months.last().is_none_or(|last| last.month != month) {
+            archive.months.push(ArchiveMonth {
+                year,
+                month,
+                url: format!("{ARCHIVES_OUTPUT}/{year}/{month:02}/"),
+                count: 0,
+                posts: Vec::new(),
+            });
+        }
+        let archive = archive.months.last_mut().unwrap();
+        archive.count += 1;
+        archive.posts.push(post.clone());
+    }
+    years
+}
+
+/// 归档页中列出的文章：月度归档是这个月的文章，年度归档是这一年的文章，时间线是所有有日期的文章
+pub fn archive_posts(
+    years: &[ArchiveYear],
+    year: Option<&ArchiveYear>,
+    month: Option<&ArchiveMonth>,
+) -> Vec<PostInfo> {
+    match (year, month) {
+        (_, Some(month)) => month.posts.clone(),
+        (Some(year), None) => year.posts(),
+        (None, None) => years.iter().flat_map(ArchiveYear::posts).collect(),
+    }
+}
+
+/// 归档页模板，对应templates目录下的archive_template.html
+///
+/// 同一个模板用来生成所有文章的时间线（`archives/`）、年度归档（`archives/2024/`）和月度归档（`archives/2024/09/`）。
+#[derive(Clone)]
+pub struct ArchiveTemplate(TemplateEngine);
+
+impl ArchiveTemplate {
+    pub fn imports(engine: &TemplateEngine) -> Result<Self> {
+        engine.check(ARCHIVE_TEMPLATE, &[])?;
+        Ok(ArchiveTemplate(engine.clone()))
+    }
+
+    /// 归档页输入的哈希值，包括模板和所有年份、月份的文章，year和month都为None时是时间线
+    pub fn fingerprint(
+        &self,
+        site_name: &str,
+        years: &[ArchiveYear],
+        year: Option<&ArchiveYear>,
+        month: Option<&ArchiveMonth>,
+    ) -> String {
+        let url = month
+            .map(|month| month.url.as_str())
+            .or(year.map(|year| year.url.as_str()))
+            .unwrap_or_default();
+        ContentHash::new()
+            .update(self.0.fingerprint())
+            .update(site_name)
+            .update(serde_json::to_string(years).unwrap_or_default())
+            .update(url)
+            .finish()
+    }
+
+    /// 渲染归档页，year和month都为None时渲染时间线，root是页面到网站根目录的相对路径
+    pub fn render(
+        &self,
+        site_name: &str,
+        years: &[ArchiveYear],
+        year: Option<&ArchiveYear>,
+        month: Option<&ArchiveMonth>,
+        root: &str,
+        output: impl AsRef<Path>,
+    ) -> Result<String> {
+        let posts = archive_posts(years, year, month);
+        let ctx = context! {
+            site_name,
+            root,
+            years,
+            year,
+            month,
+            posts,
+        };
+        self.0.render(ARCHIVE_TEMPLATE, ctx, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::build::dates::PostDate;
+    use chrono_tz::Tz;
+
+    #[test]
+    fn test_collect_archives() {
+        let post = |title: &str, date: &str| PostInfo {
+            title: title.to_string(),
+            date: date.to_string(),
+            datetime: PostDate::parse(date, Tz::UTC),
+            url: format!("articles/{title}"),
+            excerpt: String::new(),
+            description: None,
+            tags: Vec::new(),
+            draft: false,
+        };
+        let posts = [
+            post("比特币", "2023.12.30"),
+            post("以太坊", "2024-09-07"),
+            post("没有日期", "----.--.--"),
+            post("闪电网络", "2024/09/20"),
+            post("所有权", "2024.01.02"),
+        ];
+        let years = collect_archives(&posts);
+        let summary = years
+            .iter()
+            .flat_map(|year| &year.months)
+            .map(|month| {
+                let titles = month.posts.iter().map(|post| post.title.as_str());
+                (month.url.as_str(), titles.collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("archives/2024/09/", vec!["闪电网络", "以太坊"]),
+                ("archives/2024/01/", vec!["所有权"]),
+                ("archives/2023/12/", vec!["比特币"]),
+            ]
+        );
+        assert_eq!(years[0].url, "archives/2024/");
+        assert_eq!(years[0].count, 3);
+    }
+}
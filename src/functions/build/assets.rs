@@ -0,0 +1,268 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::images::image_size;
+use crate::functions::build::manifest::BuildManifest;
+use crate::functions::build::offline::rewrite_attribute;
+use crate::functions::build::post_name;
+use lol_html::{RewriteStrSettings, element, rewrite_str};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 文章引用的本地文件在输出目录中的位置
+pub static ASSETS_OUTPUT: &str = "public/assets";
+
+/// 文章引用的一个本地文件，例如图片、附件
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Asset {
+    pub source: PathBuf, // 源文件路径
+    pub target: String,  // 相对于输出目录的路径
+    #[serde(default)]
+    pub size: Option<(u32, u32)>, // 图片的宽和高，不是图片时为None
+}
+
+/// 找出文章正文中引用的本地文件，把地址改写成复制到输出目录以后的地址
+///
+/// 相对路径相对于文章源文件所在的目录，例如`![](images/a.png)`引用`posts/images/a.png`，
+/// 复制到`public/assets/images/a.png`；指向其他Markdown文件的链接改写成对应文章的地址。
+/// 图片等媒体文件或者带扩展名的链接指向的文件不存在，或者引用的文件不在posts_dir中时返回错误，
+/// 列出所有有问题的地址；没有扩展名的链接（例如`../tags/rust/`）可能指向生成的页面，保持原样。
+pub fn link_assets(html: &str, post_path: impl AsRef<Path>) -> Result<(String, Vec<Asset>)> {
+    let post_path = post_path.as_ref();
+    let source_dir = post_path.parent().unwrap_or(Path::new(""));
+    let assets = RefCell::new(BTreeSet::new());
+    let problems = RefCell::new(BTreeSet::new());
+    let rewrite = |url: &str, media: bool| match resolve(url, source_dir, media) {
+        Ok(Some((url, asset))) => {
+            if let Some(asset) = asset {
+                assets.borrow_mut().insert(asset);
+            }
+            url
+        }
+        Ok(None) => url.to_string(),
+        Err(problem) => {
+            problems.borrow_mut().insert(problem);
+            url.to_string()
+        }
+    };
+
+    let link = |url: &str| rewrite(url, false);
+    let media = |url: &str| rewrite(url, true);
+
+    let html = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("a[href]", |el| rewrite_attribute(el, "href", &link)),
+                element!(
+                    "img[src], audio[src], video[src], source[src], embed[src]",
+                    |el| { rewrite_attribute(el, "src", &media) }
+                ),
+                element!("video[poster]", |el| rewrite_attribute(
+                    el, "poster", &media
+                )),
+                element!("object[data]", |el| rewrite_attribute(el, "data", &media)),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    )
+    .map_err(|e| PackpalError::render(post_path, e))?;
+
+    let problems = problems.into_inner();
+    if !problems.is_empty() {
+        return Err(PackpalError::render(
+            post_path,
+            format!(
+                "文章中引用的本地文件有问题：\n{}",
+                problems.into_iter().collect::<Vec<_>>().join("\n")
+            ),
+        ));
+    }
+    Ok((html, assets.into_inner().into_iter().collect()))
+}
+
+/// 把文章引用的本地文件复制到输出目录，并记录到构建清单中
+pub fn copy_assets<'a>(
+    assets: impl IntoIterator<Item = &'a Asset>,
+    out_dir: impl AsRef<Path>,
+    manifest: &mut BuildManifest,
+) -> Result<()> {
+    let out_dir = out_dir.as_ref();
+    // 多篇文章可能引用同一个文件
+    let assets = assets
+        .into_iter()
+        .map(|asset| (&asset.target, &asset.source))
+        .collect::<BTreeMap<_, _>>();
+    for (target, source) in assets {
+        let data = fs::read(source).map_err(|e| PackpalError::io(source, e))?;
+        let target = out_dir.join(target);
+        if !fs::read(&target).is_ok_and(|old| old == data) {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).map_err(|e| PackpalError::io(parent, e))?;
+            }
+            fs::write(&target, data).map_err(|e| PackpalError::io(&target, e))?;
+        }
+        manifest.record(out_dir, target);
+    }
+    Ok(())
+}
+
+// 解析文章中的一个地址
+//
+// 不是本地文件（例如外部链接、页内锚点、绝对路径、生成的页面）时返回None；
+// 是本地文件时返回改写后的地址，以及需要复制的文件（指向其他文章时不需要复制）。
+// media表示地址来自<img>等媒体标签，这时必须是一个存在的文件
+fn resolve(
+    url: &str,
+    source_dir: &Path,
+    media: bool,
+) -> std::result::Result<Option<(String, Option<Asset>)>, String> {
+    let trimmed = url.trim();
+    let has_scheme = trimmed
+        .split_once(':')
+        .is_some_and(|(scheme, _)| !scheme.contains('/'));
+    if trimmed.is_empty() || has_scheme || trimmed.starts_with(['#', '/', '?']) {
+        return Ok(None);
+    }
+
+    let (path, suffix) = trimmed.split_at(trimmed.find(['?', '#']).unwrap_or(trimmed.len()));
+    let path = percent_decode(path);
+    let source = source_dir.join(&path);
+    if !source.is_file() {
+        // 没有扩展名的链接，例如`../tags/rust/`、`../articles/以太坊`，指向的是生成的页面
+        let has_extension = Path::new(&path).extension().is_some() && !path.ends_with('/');
+        if !media && !has_extension {
+            return Ok(None);
+        }
+        return Err(format!("{path}：找不到文件{}", source.display()));
+    }
+
+    // 指向其他Markdown文件的链接改成对应文章的地址，文章页面都在同一个目录下
+    if source.extension().is_some_and(|ext| ext == "md") {
+        let file_name = source.file_name().unwrap_or_default().to_string_lossy();
+        let url = format!("{}{suffix}", percent_encode(&post_name(&file_name)));
+        return Ok(Some((url, None)));
+    }
+
+    // 文章源文件都在posts_dir下，引用的文件也必须在posts_dir中，这样才能保持原来的目录结构
+    let canonical = |path: &Path| {
+        fs::canonicalize(path).map_err(|e| format!("{}：无法读取：{e}", path.display()))
+    };
+    let root = canonical(source_dir)?;
+    let file = canonical(&source)?;
+    let relative = file
+        .strip_prefix(&root)
+        .map_err(|_| format!("{path}：只能引用文章所在目录（及其子目录）中的文件"))?
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    let target = format!("{ASSETS_OUTPUT}/{relative}");
+    // 文章页面在articles目录下，需要先回到网站根目录
+    let url = format!("../{}{suffix}", percent_encode(&target));
+    let size = image_size(&source);
+    Ok(Some((
+        url,
+        Some(Asset {
+            source,
+            target,
+            size,
+        }),
+    )))
+}
+
+/// 解码URL中的%XX，例如`%E6%AF%94`解码成“比”
+pub fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// 转义路径中在URL里有特殊含义的字符，中文等字符保持原样
+pub fn percent_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            ' ' => encoded.push_str("%20"),
+            '"' => encoded.push_str("%22"),
+            '#' => encoded.push_str("%23"),
+            '%' => encoded.push_str("%25"),
+            '?' => encoded.push_str("%3F"),
+            _ => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::TEST_ROOT;
+
+    #[test]
+    fn test_link_assets() {
+        let dir = format!("{TEST_ROOT}test_link_assets");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(format!("{dir}/posts/images")).unwrap();
+        fs::write(format!("{dir}/posts/images/区块 结构.png"), "png").unwrap();
+        fs::write(format!("{dir}/posts/白皮书.pdf"), "pdf").unwrap();
+        fs::write(format!("{dir}/posts/以太坊.md"), "# 以太坊").unwrap();
+        fs::write(format!("{dir}/secret.txt"), "").unwrap();
+        let post = format!("{dir}/posts/比特币.md");
+
+        let html = concat!(
+            r#"<p><img src="./images/%E5%8C%BA%E5%9D%97%20%E7%BB%93%E6%9E%84.png" alt="区块">"#,
+            r#"<a href="白皮书.pdf#page=2">白皮书</a><a href="以太坊.md#共识">以太坊</a>"#,
+            r##"<a href="https://bitcoin.org/">官网</a><a href="#背景">背景</a>"##,
+            r#"<a href="mailto:a@b.com">邮件</a></p>"#
+        );
+        let (html, assets) = link_assets(html, &post).unwrap();
+        assert!(html.contains(r#"src="../public/assets/images/区块%20结构.png""#));
+        assert!(html.contains(r#"href="../public/assets/白皮书.pdf#page=2""#));
+        assert!(html.contains(r#"href="以太坊#共识""#));
+        assert!(html.contains(r#"href="https://bitcoin.org/""#));
+        assert!(html.contains(r##"href="#背景""##));
+        assert_eq!(assets.len(), 2);
+
+        let out_dir = PathBuf::from(format!("{dir}/build"));
+        let mut manifest = BuildManifest::default();
+        copy_assets(&assets, &out_dir, &mut manifest).unwrap();
+        assert!(out_dir.join("public/assets/images/区块 结构.png").exists());
+        assert!(manifest.contains("public/assets/白皮书.pdf"));
+
+        // 指向标签页等生成页面的链接保持原样
+        let html = r#"<a href="../tags/rust/">Rust</a><a href="../archives/2024/#九月">九月</a>"#;
+        let (linked, assets) = link_assets(html, &post).unwrap();
+        assert_eq!(linked, html);
+        assert!(assets.is_empty());
+
+        // 找不到的文件（媒体文件或者带扩展名的链接）和posts_dir之外的文件都会报错
+        let html = concat!(
+            r#"<img src="不存在.png"><a href="../secret.txt">秘密</a>"#,
+            r#"<a href="附件.zip">附件</a><img src="images/">"#
+        );
+        let err = link_assets(html, &post).unwrap_err().to_string();
+        assert!(err.contains("不存在.png"));
+        assert!(err.contains("../secret.txt"));
+        assert!(err.contains("附件.zip"));
+        assert!(err.contains("images/："));
+    }
+}
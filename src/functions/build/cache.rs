@@ -0,0 +1,189 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::assets::Asset;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// 构建缓存的文件名，和构建清单一样保存在输出目录的根目录下
+pub static CACHE_FILE: &str = ".packpal_cache.json";
+
+/// 构建缓存，记录上一次构建时每个页面的输入的哈希值
+///
+/// 输入（源文件、元数据、模板等）没有变化，并且输出文件还在的页面不需要重新生成。
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct BuildCache {
+    posts: BTreeMap<String, String>, // 文章名 -> 文章输入的哈希值
+    index: Option<String>,           // 主页输入的哈希值
+    #[serde(default)]
+    assets: BTreeMap<String, Vec<Asset>>, // 文章名 -> 文章引用的本地文件，没有重新渲染的文章也要复制这些文件
+    #[serde(default)]
+    images: BTreeMap<String, String>, // 图片在输出目录中的路径 -> 生成各个尺寸时的哈希值
+    #[serde(default)]
+    contents: BTreeMap<String, String>, // 文章名 -> 文章正文的HTML，只在订阅源需要全文时保存
+    #[serde(default)]
+    listings: BTreeMap<String, String>, // 标签页、归档页的地址 -> 页面输入的哈希值
+}
+
+impl BuildCache {
+    /// 读取out_dir下的缓存
+    ///
+    /// 缓存只影响构建速度，缓存不存在或者无法读取时返回空缓存，所有页面都会重新生成
+    pub fn load(out_dir: impl AsRef<Path>) -> Self {
+        fs::read_to_string(out_dir.as_ref().join(CACHE_FILE))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, out_dir: impl AsRef<Path>) -> Result<()> {
+        let path = out_dir.as_ref().join(CACHE_FILE);
+        let json =
+            serde_json::to_string_pretty(self).map_err(|e| PackpalError::metadata(&path, e))?;
+        fs::write(&path, json).map_err(|e| PackpalError::io(&path, e))
+    }
+
+    pub fn post(&self, name: &str) -> Option<&str> {
+        self.posts.get(name).map(String::as_str)
+    }
+
+    pub fn set_post(&mut self, name: impl ToString, hash: String) {
+        self.posts.insert(name.to_string(), hash);
+    }
+
+    pub fn assets(&self, name: &str) -> &[Asset] {
+        self.assets.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn set_assets(&mut self, name: impl ToString, assets: Vec<Asset>) {
+        if !assets.is_empty() {
+            self.assets.insert(name.to_string(), assets);
+        }
+    }
+
+    pub fn content(&self, name: &str) -> Option<&str> {
+        self.contents.get(name).map(String::as_str)
+    }
+
+    pub fn set_content(&mut self, name: impl ToString, content: String) {
+        self.contents.insert(name.to_string(), content);
+    }
+
+    pub fn image(&self, target: &str) -> Option<&str> {
+        self.images.get(target).map(String::as_str)
+    }
+
+    pub fn set_image(&mut self, target: impl ToString, hash: String) {
+        self.images.insert(target.to_string(), hash);
+    }
+
+    pub fn listing(&self, url: &str) -> Option<&str> {
+        self.listings.get(url).map(String::as_str)
+    }
+
+    pub fn set_listing(&mut self, url: impl ToString, hash: String) {
+        self.listings.insert(url.to_string(), hash);
+    }
+
+    pub fn index(&self) -> Option<&str> {
+        self.index.as_deref()
+    }
+
+    pub fn set_index(&mut self, hash: String) {
+        self.index = Some(hash);
+    }
+}
+
+/// 计算页面输入的哈希值
+///
+/// 每一部分之前都写入它的长度，避免不同的输入拼接起来以后变得相同。
+/// packpal的版本号也算在里面，升级packpal以后所有页面都会重新生成。
+pub struct ContentHash(Sha256);
+
+impl ContentHash {
+    pub fn new() -> Self {
+        ContentHash(Sha256::new()).update(env!("CARGO_PKG_VERSION"))
+    }
+
+    pub fn update(mut self, part: impl AsRef<[u8]>) -> Self {
+        let part = part.as_ref();
+        self.0.update((part.len() as u64).to_le_bytes());
+        self.0.update(part);
+        self
+    }
+
+    pub fn finish(self) -> String {
+        self.0
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+/// 目录下所有文件（包括子目录）的路径和内容的哈希值，用来判断模板有没有变化
+pub fn hash_dir(dir: impl AsRef<Path>) -> Result<String> {
+    let dir = dir.as_ref();
+    let mut files = BTreeMap::new();
+    collect_files(dir, dir, &mut files)?;
+    Ok(files
+        .into_iter()
+        .fold(ContentHash::new(), |hash, (path, content)| {
+            hash.update(path).update(content)
+        })
+        .finish())
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut BTreeMap<String, Vec<u8>>) -> Result<()> {
+    for entry in fs::read_dir(dir).map_err(|e| PackpalError::io(dir, e))? {
+        let path = entry.map_err(|e| PackpalError::io(dir, e))?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else {
+            let content = fs::read(&path).map_err(|e| PackpalError::io(&path, e))?;
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            files.insert(relative.to_string_lossy().replace('\\', "/"), content);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::TEST_ROOT;
+
+    #[test]
+    fn test_hash_and_cache() {
+        assert_eq!(
+            ContentHash::new().update("ab").update("c").finish(),
+            ContentHash::new().update("ab").update("c").finish()
+        );
+        assert_ne!(
+            ContentHash::new().update("ab").update("c").finish(),
+            ContentHash::new().update("a").update("bc").finish()
+        );
+
+        let dir = format!("{TEST_ROOT}test_hash_and_cache");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(format!("{dir}/templates/partials")).unwrap();
+        fs::write(format!("{dir}/templates/index.html"), "主页").unwrap();
+        let before = hash_dir(format!("{dir}/templates")).unwrap();
+        fs::write(format!("{dir}/templates/partials/card.html"), "卡片").unwrap();
+        assert_ne!(before, hash_dir(format!("{dir}/templates")).unwrap());
+
+        // 缓存不存在或者损坏时当作空缓存
+        assert_eq!(BuildCache::load(&dir), BuildCache::default());
+        fs::write(format!("{dir}/{CACHE_FILE}"), "{ 损坏的缓存").unwrap();
+        assert_eq!(BuildCache::load(&dir), BuildCache::default());
+
+        let mut cache = BuildCache::default();
+        cache.set_post("比特币", before.clone());
+        cache.set_index(before.clone());
+        cache.save(&dir).unwrap();
+        let cache = BuildCache::load(&dir);
+        assert_eq!(cache.post("比特币"), Some(before.as_str()));
+        assert_eq!(cache.index(), Some(before.as_str()));
+    }
+}
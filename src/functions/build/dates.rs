@@ -0,0 +1,188 @@
+use crate::error::{PackpalError, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Serialize, Serializer};
+use std::cmp::Ordering;
+use std::path::Path;
+
+/// 模板中日期的默认格式，例如`2024.09.07`
+pub static DEFAULT_DATE_FORMAT: &str = "%Y.%m.%d";
+/// 没有写时区的日期默认使用的时区
+pub static DEFAULT_TIMEZONE: &str = "UTC";
+/// 没有日期的文章在模板中显示的日期
+pub static MISSING_DATE: &str = "----.--.--";
+
+// 日期之间可以使用的分隔符
+const SEPARATORS: [&str; 3] = ["-", ".", "/"];
+
+/// 文章的发表时间
+///
+/// 只写了日期时按当天零点计算；没有写时区时使用config.json中的timezone，默认是UTC。
+/// 在模板中输出成RFC 3339格式的字符串，例如`2024-09-07T20:30:00+08:00`。
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PostDate(DateTime<FixedOffset>);
+
+impl PostDate {
+    /// 解析文章的日期，支持：
+    ///
+    /// - 只有日期：`2024.09.07`、`2024-09-07`、`2024/09/07`、`2024年9月7日`
+    /// - 日期和时间：`2024-09-07 20:30`、`2024-09-07 20:30:00`，日期和时间之间也可以用`T`
+    /// - 带时区：`2024-09-07T20:30:00+08:00`、`2024-09-07 20:30 +0800`、`2024-09-07T12:30:00Z`
+    /// - RFC 2822：`Sat, 07 Sep 2024 20:30:00 +0800`
+    ///
+    /// 没有写时区的日期按timezone计算。
+    pub fn parse(date: &str, timezone: Tz) -> Option<Self> {
+        let date = date.trim();
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(date) {
+            return Some(PostDate(datetime));
+        }
+        if let Ok(datetime) = DateTime::parse_from_rfc2822(date) {
+            return Some(PostDate(datetime));
+        }
+        for day in date_formats() {
+            for time in ["%H:%M:%S", "%H:%M"] {
+                for separator in [" ", "T"] {
+                    let format = format!("{day}{separator}{time}");
+                    for zone in ["%:z", " %:z", "%z", " %z", "Z"] {
+                        let format = format!("{format}{zone}");
+                        if zone == "Z" {
+                            if let Ok(datetime) = NaiveDateTime::parse_from_str(date, &format) {
+                                return Some(PostDate(datetime.and_utc().fixed_offset()));
+                            }
+                        } else if let Ok(datetime) = DateTime::parse_from_str(date, &format) {
+                            return Some(PostDate(datetime));
+                        }
+                    }
+                    if let Ok(datetime) = NaiveDateTime::parse_from_str(date, &format) {
+                        return Self::local(datetime, timezone);
+                    }
+                }
+            }
+            if let Ok(day) = NaiveDate::parse_from_str(date, &day) {
+                return Self::local(day.and_hms_opt(0, 0, 0)?, timezone);
+            }
+        }
+        None
+    }
+
+    // 没有时区的时间按timezone计算，夏令时切换时重复的时间取较早的一个
+    fn local(datetime: NaiveDateTime, timezone: Tz) -> Option<Self> {
+        timezone
+            .from_local_datetime(&datetime)
+            .earliest()
+            .map(|datetime| PostDate(datetime.fixed_offset()))
+    }
+
+    pub fn datetime(&self) -> DateTime<FixedOffset> {
+        self.0
+    }
+
+    /// 发表时间是不是还没到
+    pub fn is_future(&self) -> bool {
+        self.0 > Utc::now()
+    }
+
+    /// 按strftime格式输出，例如`%Y年%m月%d日`
+    pub fn format(&self, format: &str) -> String {
+        self.0.format(format).to_string()
+    }
+}
+
+impl Ord for PostDate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl PartialOrd for PostDate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Serialize for PostDate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_rfc3339())
+    }
+}
+
+// 只有日期部分的格式，年月日之间可以用SEPARATORS中的任意一个分隔，也可以写成中文
+fn date_formats() -> impl Iterator<Item = String> {
+    SEPARATORS
+        .iter()
+        .map(|separator| format!("%Y{separator}%m{separator}%d"))
+        .chain([String::from("%Y年%m月%d日")])
+}
+
+/// 解析config.json中的timezone，可以是`UTC`或者`Asia/Shanghai`这样的IANA时区名
+pub fn parse_timezone(timezone: &str, path: impl AsRef<Path>) -> Result<Tz> {
+    timezone.trim().parse().map_err(|_| {
+        PackpalError::config(
+            path,
+            format!("timezone“{timezone}”不是有效的时区，例如可以写成“UTC”或者“Asia/Shanghai”"),
+        )
+    })
+}
+
+/// 检查config.json中的date_format是不是有效的strftime格式
+pub fn validate_format(format: &str, path: impl AsRef<Path>) -> Result<()> {
+    if format.is_empty() || StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(PackpalError::config(
+            path,
+            format!("date_format“{format}”不是有效的日期格式，例如可以写成“%Y.%m.%d”"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_post_date() {
+        let rfc3339 = |date: &str| PostDate::parse(date, Tz::UTC).map(|date| date.0.to_rfc3339());
+        assert_eq!(
+            rfc3339("2024-09-07T20:30:00+08:00").as_deref(),
+            Some("2024-09-07T20:30:00+08:00")
+        );
+        assert_eq!(
+            rfc3339("2024/09/07 20:30 +0800").as_deref(),
+            Some("2024-09-07T20:30:00+08:00")
+        );
+        assert_eq!(
+            rfc3339("2024.09.07T12:30:00Z").as_deref(),
+            Some("2024-09-07T12:30:00+00:00")
+        );
+        assert_eq!(
+            rfc3339("Sat, 07 Sep 2024 20:30:00 +0800").as_deref(),
+            Some("2024-09-07T20:30:00+08:00")
+        );
+
+        // 没有时区时按timezone计算
+        let local = |date: &str| PostDate::parse(date, Tz::UTC).map(|date| date.0.naive_local());
+        let day = NaiveDate::from_ymd_opt(2024, 9, 7).unwrap();
+        for date in ["2024.09.07", "2024-9-7", " 2024/09/07 ", "2024年9月7日"] {
+            assert_eq!(local(date), day.and_hms_opt(0, 0, 0), "{date}");
+        }
+        assert_eq!(local("2024-09-07 20:30"), day.and_hms_opt(20, 30, 0));
+
+        for date in ["----.--.--", "2024-13-01", "2024-09-07 25:00", "昨天"] {
+            assert_eq!(PostDate::parse(date, Tz::UTC), None, "{date}");
+        }
+        let date = PostDate::parse("2024-09-07", Tz::UTC).unwrap();
+        assert_eq!(date.format("%Y年%m月%d日"), "2024年09月07日");
+        assert_eq!(date.0.to_rfc3339(), "2024-09-07T00:00:00+00:00");
+        let shanghai = parse_timezone("Asia/Shanghai", "config.json").unwrap();
+        let date = PostDate::parse("2024-09-07 20:30", shanghai).unwrap();
+        assert_eq!(date.0.to_rfc3339(), "2024-09-07T20:30:00+08:00");
+        // 夏令时开始时跳过的时间不存在
+        let new_york = parse_timezone("America/New_York", "config.json").unwrap();
+        assert_eq!(PostDate::parse("2024-03-10 02:30", new_york), None);
+        assert!(parse_timezone("Local", "config.json").is_err());
+
+        assert!(validate_format("%Y.%m.%d", "config.json").is_ok());
+        assert!(validate_format("%Y.%m.%Q", "config.json").is_err());
+    }
+}
@@ -0,0 +1,452 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::assets::percent_encode;
+use crate::functions::build::highlight::escape_html;
+use crate::functions::build::index::{PostInfo, newest_first};
+use crate::functions::build::manifest::BuildManifest;
+use crate::functions::build::offline::rewrite_attribute;
+use chrono::{DateTime, FixedOffset};
+use lol_html::{RewriteStrSettings, element, rewrite_str};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// RSS订阅源的文件名
+pub static RSS_FILE: &str = "feed.xml";
+/// Atom订阅源的文件名
+pub static ATOM_FILE: &str = "atom.xml";
+
+/// 订阅源的选项，对应config.json中的feed
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct FeedOptions {
+    pub enabled: bool,        // 是否生成feed.xml和atom.xml，需要设置site_url
+    pub content: FeedContent, // 订阅源中放文章摘要还是全文
+    pub limit: usize,         // 订阅源中最多放多少篇最新的文章，0表示全部
+}
+
+impl Default for FeedOptions {
+    fn default() -> Self {
+        FeedOptions {
+            enabled: true,
+            content: FeedContent::Summary,
+            limit: 20,
+        }
+    }
+}
+
+/// 订阅源中文章的内容
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedContent {
+    /// 文章简介（description），没有简介时用摘要
+    #[default]
+    Summary,
+    /// 渲染后的全文
+    Full,
+}
+
+impl FeedOptions {
+    /// 是否需要文章渲染后的全文
+    pub fn full_content(&self) -> bool {
+        self.enabled && self.content == FeedContent::Full
+    }
+}
+
+/// 订阅源中的一篇文章
+#[derive(Debug, Clone)]
+pub struct FeedEntry {
+    pub info: PostInfo,
+    pub content: Option<String>, // 文章正文的HTML，其中的地址相对于articles目录；只放摘要时为None
+}
+
+/// 订阅源，同时生成RSS 2.0和Atom两种格式
+#[derive(Debug, Clone)]
+pub struct Feed {
+    pub title: String,
+    pub description: String,
+    pub author: String,
+    pub email: String,
+    pub site_url: String, // 网站的完整地址，以`/`结尾
+    pub entries: Vec<FeedEntry>,
+}
+
+impl Feed {
+    /// 按日期从新到旧排列文章，只保留最新的limit篇；没有日期的文章排在最后
+    pub fn new(
+        title: String,
+        description: String,
+        author: String,
+        email: String,
+        site_url: &str,
+        mut entries: Vec<FeedEntry>,
+        options: &FeedOptions,
+    ) -> Self {
+        entries.sort_by(|a, b| newest_first(&a.info, &b.info));
+        if options.limit > 0 {
+            entries.truncate(options.limit);
+        }
+        Feed {
+            title,
+            description,
+            author,
+            email,
+            site_url: absolute_url(site_url, ""),
+            entries,
+        }
+    }
+
+    /// 生成feed.xml和atom.xml，内容没有变化时不重写文件
+    pub fn write_into(
+        &self,
+        out_dir: impl AsRef<Path>,
+        manifest: &mut BuildManifest,
+    ) -> Result<()> {
+        let out_dir = out_dir.as_ref();
+        for (file, xml) in [(RSS_FILE, self.rss()?), (ATOM_FILE, self.atom()?)] {
+            let path = out_dir.join(file);
+            if fs::read_to_string(&path).ok() != Some(xml.clone()) {
+                fs::write(&path, xml).map_err(|e| PackpalError::io(&path, e))?;
+            }
+            manifest.record(out_dir, path);
+        }
+        Ok(())
+    }
+
+    /// RSS 2.0格式的订阅源
+    pub fn rss(&self) -> Result<String> {
+        let mut items = String::new();
+        for entry in &self.entries {
+            let link = self.link(&entry.info);
+            items.push_str("    <item>\n");
+            items.push_str(&format!(
+                "      <title>{}</title>\n",
+                escape_html(&entry.info.title)
+            ));
+            items.push_str(&format!("      <link>{}</link>\n", escape_html(&link)));
+            items.push_str(&format!(
+                "      <guid isPermaLink=\"true\">{}</guid>\n",
+                escape_html(&link)
+            ));
+            if let Some(date) = entry.info.datetime {
+                items.push_str(&format!(
+                    "      <pubDate>{}</pubDate>\n",
+                    date.datetime().to_rfc2822()
+                ));
+            }
+            for tag in &entry.info.tags {
+                items.push_str(&format!(
+                    "      <category>{}</category>\n",
+                    escape_html(tag)
+                ));
+            }
+            items.push_str(&format!(
+                "      <description>{}</description>\n",
+                escape_html(&self.content(entry)?)
+            ));
+            items.push_str("    </item>\n");
+        }
+
+        let updated = self
+            .updated()
+            .map(|date| format!("    <lastBuildDate>{}</lastBuildDate>\n", date.to_rfc2822()))
+            .unwrap_or_default();
+        Ok(format!(
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n",
+                "  <channel>\n",
+                "    <title>{title}</title>\n",
+                "    <link>{site_url}</link>\n",
+                "    <description>{description}</description>\n",
+                "    <atom:link href=\"{site_url}{file}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+                "    <generator>packpal</generator>\n",
+                "{updated}{items}",
+                "  </channel>\n",
+                "</rss>\n"
+            ),
+            title = escape_html(&self.title),
+            site_url = escape_html(&self.site_url),
+            description = escape_html(&self.description),
+            file = RSS_FILE,
+            updated = updated,
+            items = items,
+        ))
+    }
+
+    /// Atom格式的订阅源
+    pub fn atom(&self) -> Result<String> {
+        // Atom要求每一项都有更新时间，没有日期的文章使用整个订阅源的更新时间；
+        // 所有文章都没有日期时使用固定的1970-01-01，文章没有变化时订阅源的内容也不变
+        let updated = self
+            .updated()
+            .unwrap_or_else(|| DateTime::UNIX_EPOCH.fixed_offset())
+            .to_rfc3339();
+        let mut entries = String::new();
+        for entry in &self.entries {
+            let link = escape_html(&self.link(&entry.info));
+            let date = entry.info.datetime.map(|date| date.datetime().to_rfc3339());
+            let date = date.as_deref().unwrap_or(&updated);
+            entries.push_str("  <entry>\n");
+            entries.push_str(&format!(
+                "    <title>{}</title>\n",
+                escape_html(&entry.info.title)
+            ));
+            entries.push_str(&format!("    <link href=\"{link}\"/>\n"));
+            entries.push_str(&format!("    <id>{link}</id>\n"));
+            entries.push_str(&format!("    <published>{date}</published>\n"));
+            entries.push_str(&format!("    <updated>{date}</updated>\n"));
+            for tag in &entry.info.tags {
+                entries.push_str(&format!("    <category term=\"{}\"/>\n", escape_html(tag)));
+            }
+            let element = if entry.content.is_some() {
+                "content"
+            } else {
+                "summary"
+            };
+            entries.push_str(&format!(
+                "    <{element} type=\"html\">{}</{element}>\n",
+                escape_html(&self.content(entry)?)
+            ));
+            entries.push_str("  </entry>\n");
+        }
+
+        let email = match self.email.is_empty() {
+            true => String::new(),
+            false => format!("<email>{}</email>", escape_html(&self.email)),
+        };
+        let subtitle = match self.description.is_empty() {
+            true => String::new(),
+            false => format!(
+                "  <subtitle>{}</subtitle>\n",
+                escape_html(&self.description)
+            ),
+        };
+        Ok(format!(
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+                "  <title>{title}</title>\n",
+                "{subtitle}",
+                "  <link href=\"{site_url}\"/>\n",
+                "  <link href=\"{site_url}{file}\" rel=\"self\" type=\"application/atom+xml\"/>\n",
+                "  <id>{site_url}</id>\n",
+                "  <updated>{updated}</updated>\n",
+                "  <author><name>{author}</name>{email}</author>\n",
+                "  <generator>packpal</generator>\n",
+                "{entries}",
+                "</feed>\n"
+            ),
+            title = escape_html(&self.title),
+            subtitle = subtitle,
+            site_url = escape_html(&self.site_url),
+            file = ATOM_FILE,
+            updated = updated,
+            author = escape_html(&self.author),
+            email = email,
+            entries = entries,
+        ))
+    }
+
+    // 文章的完整地址
+    fn link(&self, post: &PostInfo) -> String {
+        absolute_url(&self.site_url, &post.url)
+    }
+
+    // 文章在订阅源中的内容，全文中的相对地址都改成完整地址，阅读器才能加载图片、打开链接
+    fn content(&self, entry: &FeedEntry) -> Result<String> {
+        let info = &entry.info;
+        match &entry.content {
+            Some(html) => absolutize(html, &self.link(info)),
+            None => Ok(info
+                .description
+                .clone()
+                .unwrap_or_else(|| info.excerpt.clone())),
+        }
+    }
+
+    // 最新一篇文章的日期
+    fn updated(&self) -> Option<DateTime<FixedOffset>> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.info.datetime)
+            .max()
+            .map(|date| date.datetime())
+    }
+}
+
+/// 网站中path（相对于网站根目录）的完整地址，path为空时返回以`/`结尾的网站地址
+pub fn absolute_url(site_url: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        site_url.trim_end_matches('/'),
+        percent_encode(path)
+    )
+}
+
+// 把HTML中的相对地址改成相对于page_url的完整地址
+fn absolutize(html: &str, page_url: &str) -> Result<String> {
+    let rewrite = |url: &str| resolve_url(page_url, url);
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("a[href]", |el| rewrite_attribute(el, "href", &rewrite)),
+                element!(
+                    "img[src], audio[src], video[src], source[src], embed[src]",
+                    |el| { rewrite_attribute(el, "src", &rewrite) }
+                ),
+                element!("video[poster]", |el| rewrite_attribute(
+                    el, "poster", &rewrite
+                )),
+                element!("object[data]", |el| rewrite_attribute(el, "data", &rewrite)),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    )
+    .map_err(|e| PackpalError::render(page_url, e))
+}
+
+// 按照浏览器的规则，把相对于base的地址url解析成完整地址，base必须是完整地址
+fn resolve_url(base: &str, url: &str) -> String {
+    let url = url.trim();
+    let has_scheme = url
+        .split_once(':')
+        .is_some_and(|(scheme, _)| !scheme.contains(['/', '?', '#']));
+    if has_scheme || url.starts_with("//") {
+        return url.to_string();
+    }
+    let base = base.split('#').next().unwrap_or_default();
+    if url.is_empty() || url.starts_with('#') {
+        return format!("{base}{url}");
+    }
+    let (origin, path) = match base.find("://") {
+        Some(i) => base.split_at(base[i + 3..].find('/').map_or(base.len(), |j| i + 3 + j)),
+        None => ("", base),
+    };
+    let path = path.split('?').next().unwrap_or_default();
+    if url.starts_with('?') {
+        return format!("{origin}{path}{url}");
+    }
+
+    let (url_path, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+    let joined = match url_path.starts_with('/') {
+        true => url_path.to_string(),
+        false => format!(
+            "{}{url_path}",
+            &path[..path.rfind('/').map_or(0, |i| i + 1)]
+        ),
+    };
+    let mut segments: Vec<&str> = Vec::new();
+    let parts = joined.split('/').skip(1).collect::<Vec<_>>();
+    for (i, part) in parts.iter().enumerate() {
+        let last = i + 1 == parts.len();
+        match *part {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            part => segments.push(part),
+        }
+        // 以`.`或`..`结尾的地址指向目录
+        if last && matches!(*part, "." | "..") {
+            segments.push("");
+        }
+    }
+    format!("{origin}/{}{suffix}", segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::build::dates::PostDate;
+    use chrono_tz::Tz;
+
+    #[test]
+    fn test_feed() {
+        let post = |title: &str, date: &str, content: Option<&str>| FeedEntry {
+            info: PostInfo {
+                title: title.to_string(),
+                date: date.to_string(),
+                datetime: PostDate::parse(date, Tz::UTC),
+                url: format!("articles/{title}"),
+                excerpt: String::from("摘要"),
+                description: None,
+                tags: vec![String::from("区块链 & 密码学")],
+                draft: false,
+            },
+            content: content.map(str::to_string),
+        };
+        let entries = vec![
+            post("旧文章", "2023.01.02", None),
+            post("没有日期", "----.--.--", None),
+            post(
+                "比特币 入门",
+                "2024-09-07",
+                Some(
+                    r#"<p><img src="../public/assets/a.png"><a href="以太坊#共识">以太坊</a></p>"#,
+                ),
+            ),
+        ];
+        let options = FeedOptions::default();
+        let feed = Feed::new(
+            String::from("我的博客"),
+            String::new(),
+            String::from("我的博客"),
+            String::new(),
+            "https://example.com/blog",
+            entries,
+            &options,
+        );
+        assert_eq!(feed.entries[0].info.title, "比特币 入门");
+        assert_eq!(feed.entries[2].info.title, "没有日期");
+
+        let rss = feed.rss().unwrap();
+        assert!(rss.contains("<link>https://example.com/blog/articles/比特币%20入门</link>"));
+        assert!(rss.contains("<category>区块链 &amp; 密码学</category>"));
+        assert!(
+            rss.contains(
+                "&lt;img src=&quot;https://example.com/blog/public/assets/a.png&quot;&gt;"
+            )
+        );
+        assert!(rss.contains("href=&quot;https://example.com/blog/articles/以太坊#共识&quot;"));
+        assert!(rss.contains("<description>摘要</description>"));
+
+        let atom = feed.atom().unwrap();
+        assert!(atom.contains("<id>https://example.com/blog/</id>"));
+        assert!(atom.contains("<updated>2024-09-07T00:00:00"));
+        assert!(atom.contains("<content type=\"html\">"));
+        assert!(atom.contains("<summary type=\"html\">摘要</summary>"));
+        assert!(atom.contains("<category term=\"区块链 &amp; 密码学\"/>"));
+
+        // 所有文章都没有日期时，两次生成的订阅源完全相同
+        let undated = || {
+            Feed::new(
+                String::from("我的博客"),
+                String::new(),
+                String::from("我的博客"),
+                String::new(),
+                "https://example.com/blog",
+                vec![post("没有日期", "----.--.--", None)],
+                &options,
+            )
+            .atom()
+            .unwrap()
+        };
+        let atom = undated();
+        assert!(atom.contains("<updated>1970-01-01T00:00:00+00:00</updated>"));
+        assert_eq!(atom, undated());
+
+        assert_eq!(
+            resolve_url("https://example.com/blog/articles/a", "../b/./c.png?x=1"),
+            "https://example.com/blog/b/c.png?x=1"
+        );
+        assert_eq!(
+            resolve_url("https://example.com/blog/articles/a", "/about"),
+            "https://example.com/about"
+        );
+        assert_eq!(
+            resolve_url("https://example.com/blog/articles/a", "mailto:a@b.com"),
+            "mailto:a@b.com"
+        );
+    }
+}
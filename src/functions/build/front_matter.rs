@@ -0,0 +1,100 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::posts::PostMetadata;
+use std::path::Path;
+
+/// 从Markdown源文件开头拆出front matter，返回解析出的元数据和剩下的正文
+///
+/// 支持两种格式：
+/// - YAML，用`---`包围
+/// - TOML，用`+++`包围
+///
+/// 没有front matter时返回默认（全部为空）的元数据和原文。
+pub fn split_front_matter(path: impl AsRef<Path>, source: &str) -> Result<(PostMetadata, &str)> {
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    let Some((delimiter, front_matter, body)) = find_block(source) else {
+        return Ok((PostMetadata::default(), source));
+    };
+
+    let metadata = match delimiter {
+        "---" => serde_norway::from_str::<Option<PostMetadata>>(front_matter)
+            .map(Option::unwrap_or_default)
+            .map_err(|e| {
+                PackpalError::metadata(&path, format!("YAML front matter格式错误：{e}"))
+            })?,
+        _ => parse_toml(front_matter).map_err(|e| {
+            PackpalError::metadata(&path, format!("TOML front matter格式错误：{e}"))
+        })?,
+    };
+    Ok((metadata, body))
+}
+
+// 找到开头的front matter块，返回分隔符、块内容和剩下的正文
+fn find_block(source: &str) -> Option<(&'static str, &str, &str)> {
+    let delimiter = ["---", "+++"]
+        .into_iter()
+        .find(|d| first_line(source) == Some(*d))?;
+    let start = source.find('\n')? + 1;
+
+    let mut offset = start;
+    for line in source[start..].split_inclusive('\n') {
+        if line.trim_end() == delimiter {
+            let body = &source[offset + line.len()..];
+            return Some((delimiter, &source[start..offset], body));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+fn first_line(source: &str) -> Option<&str> {
+    source.lines().next().map(str::trim_end)
+}
+
+// TOML中不加引号的日期会被解析成日期类型，这里统一转换成字符串再交给PostMetadata
+fn parse_toml(front_matter: &str) -> std::result::Result<PostMetadata, toml::de::Error> {
+    let mut table: toml::Table = toml::from_str(front_matter)?;
+    for (_, value) in table.iter_mut() {
+        if let toml::Value::Datetime(datetime) = value {
+            *value = toml::Value::String(datetime.to_string());
+        }
+    }
+    toml::Value::Table(table).try_into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yaml_and_toml_front_matter() {
+        let yaml = "---\ntitle: 比特币白皮书\ndate: 2024.09.07\ntags: [比特币, 区块链]\ndraft: true\n---\n# 正文\n";
+        let (metadata, body) = split_front_matter("yaml.md", yaml).unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("比特币白皮书"));
+        assert_eq!(metadata.date.as_deref(), Some("2024.09.07"));
+        assert_eq!(
+            metadata.tags,
+            Some(vec!["比特币".to_string(), "区块链".to_string()])
+        );
+        assert_eq!(metadata.draft, Some(true));
+        assert_eq!(body, "# 正文\n");
+
+        let toml =
+            "+++\ntitle = \"比特币白皮书\"\ndate = 2024-09-07\ndescription = \"摘要\"\n+++\n正文";
+        let (metadata, body) = split_front_matter("toml.md", toml).unwrap();
+        assert_eq!(metadata.date.as_deref(), Some("2024-09-07"));
+        assert_eq!(metadata.description.as_deref(), Some("摘要"));
+        assert_eq!(body, "正文");
+
+        // 没有front matter，或者分隔符没有闭合时，原文保持不变
+        let plain = "# 标题\n---\n正文";
+        assert_eq!(split_front_matter("plain.md", plain).unwrap().1, plain);
+        let unclosed = "---\ntitle: 没有闭合\n";
+        assert_eq!(
+            split_front_matter("unclosed.md", unclosed).unwrap().1,
+            unclosed
+        );
+
+        let err = split_front_matter("bad.md", "---\ntags: [\n---\n").unwrap_err();
+        assert!(matches!(err, PackpalError::Metadata { .. }));
+    }
+}
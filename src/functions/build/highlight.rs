@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Color, Theme, ThemeSet};
+use syntect::html::{
+    ClassStyle, ClassedHTMLGenerator, IncludeBackground, css_for_theme_with_class_style,
+    styled_line_to_highlighted_html,
+};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+/// 代码高亮样式表的文件名，生成在输出目录的public文件夹下
+pub static HIGHLIGHT_CSS: &str = "highlight.css";
+
+// 高亮代码产生的class都带上这个前缀，避免和模板中的样式冲突
+static CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+// 语法定义和主题加载起来比较慢，整个构建过程只加载一次
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+/// 代码高亮的选项，对应config.json中的markdown.highlight
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct HighlightOptions {
+    pub enabled: bool,       // 是否在生成时高亮代码块
+    pub mode: HighlightMode, // 高亮结果的输出方式
+    pub theme: String,       // 配色主题，见theme_names
+}
+
+impl Default for HighlightOptions {
+    fn default() -> Self {
+        HighlightOptions {
+            enabled: true,
+            mode: HighlightMode::Class,
+            theme: String::from("InspiredGitHub"),
+        }
+    }
+}
+
+/// 高亮结果的输出方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightMode {
+    /// 代码中只有class，颜色写在生成的public/highlight.css中
+    Class,
+    /// 颜色直接写在每段代码的style属性里，不需要样式表，适合RSS阅读器等无法加载样式表的场合
+    Inline,
+}
+
+impl HighlightOptions {
+    /// 文章页面是否需要引用高亮样式表，只有class模式需要
+    pub fn uses_stylesheet(&self) -> bool {
+        self.enabled && self.mode == HighlightMode::Class
+    }
+
+    /// 高亮样式表的内容，不需要样式表时返回None
+    pub fn stylesheet(&self) -> Option<String> {
+        if !self.uses_stylesheet() {
+            return None;
+        }
+        css_for_theme_with_class_style(self.load_theme()?, CLASS_STYLE).ok()
+    }
+
+    fn load_theme(&self) -> Option<&'static Theme> {
+        THEMES.themes.get(&self.theme)
+    }
+}
+
+/// packpal内置的所有配色主题
+pub fn theme_names() -> Vec<&'static str> {
+    THEMES.themes.keys().map(String::as_str).collect()
+}
+
+/// 是否有这个名字的配色主题
+pub fn has_theme(name: &str) -> bool {
+    THEMES.themes.contains_key(name)
+}
+
+/// 高亮一个代码块，返回完整的`<pre>`元素
+///
+/// lang是代码块的信息字符串（例如` ```rust `中的rust），不认识的语言按纯文本输出。
+pub fn highlight(code: &str, lang: &str, options: &HighlightOptions) -> String {
+    // 信息字符串中第一个词是语言，例如`rust,ignore`、`python title="main.py"`
+    let lang = lang
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .next()
+        .unwrap_or_default();
+    let syntax = SYNTAXES
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+    let code_class = if lang.is_empty() {
+        String::new()
+    } else {
+        format!(" class=\"language-{}\"", escape_html(lang))
+    };
+
+    let highlighted = match (options.mode, options.load_theme()) {
+        (HighlightMode::Inline, Some(theme)) => highlight_inline(code, syntax, theme)
+            .map(|(html, style)| (html, format!(" style=\"{style}\""))),
+        _ => highlight_classed(code, syntax).map(|html| (html, String::new())),
+    };
+    match highlighted {
+        Some((html, style)) => {
+            format!("<pre class=\"hl-code\"{style}><code{code_class}>{html}</code></pre>\n")
+        }
+        // 语法定义中的正则表达式出错时不高亮，原样输出代码
+        None => format!(
+            "<pre><code{code_class}>{}</code></pre>\n",
+            escape_html(code)
+        ),
+    }
+}
+
+fn highlight_classed(code: &str, syntax: &SyntaxReference) -> Option<String> {
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        generator
+            .parse_html_for_line_which_includes_newline(line)
+            .ok()?;
+    }
+    Some(generator.finalize())
+}
+
+// 返回高亮后的代码和代码块的背景样式
+fn highlight_inline(
+    code: &str,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+) -> Option<(String, String)> {
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut html = String::new();
+    for line in LinesWithEndings::from(code) {
+        let regions = highlighter.highlight_line(line, &SYNTAXES).ok()?;
+        html.push_str(&styled_line_to_highlighted_html(&regions, IncludeBackground::No).ok()?);
+    }
+    let color = |color: Option<Color>, default: &str| {
+        color.map_or(default.to_string(), |c| {
+            format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b)
+        })
+    };
+    let style = format!(
+        "color:{};background-color:{}",
+        color(theme.settings.foreground, "#000000"),
+        color(theme.settings.background, "#ffffff")
+    );
+    Some((html, style))
+}
+
+/// 转义HTML中的特殊字符
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::build::markdown::{self, MarkdownOptions};
+
+    #[test]
+    fn test_highlight() {
+        let options = HighlightOptions::default();
+        let html = highlight("fn main() {}\n", "rust,ignore", &options);
+        assert!(html.starts_with("<pre class=\"hl-code\"><code class=\"language-rust\">"));
+        assert!(html.contains("<span class=\"hl-"));
+        assert!(!html.contains("style="));
+        assert!(options.stylesheet().unwrap().contains(".hl-code"));
+
+        // 不认识的语言按纯文本输出，代码中的特殊字符需要转义
+        let html = highlight("<b>&</b>\n", "不存在的语言", &options);
+        assert!(html.contains("&lt;b&gt;&amp;&lt;/b&gt;"));
+
+        let options = HighlightOptions {
+            mode: HighlightMode::Inline,
+            ..HighlightOptions::default()
+        };
+        let html = highlight("let x = 1;\n", "js", &options);
+        assert!(html.starts_with("<pre class=\"hl-code\" style=\"color:#"));
+        assert!(html.contains("<span style=\"color:"));
+        assert!(!html.contains("<span class="));
+        assert_eq!(options.stylesheet(), None);
+        assert!(has_theme("base16-ocean.dark"));
+
+        // 关闭高亮时代码块原样输出，由页面中的Prism在浏览器中高亮
+        let source = "```rust\nfn main() {}\n```";
+        let mut markdown = MarkdownOptions::default();
+        assert!(!markdown::render(source, &markdown).prism);
+        markdown.highlight.enabled = false;
+        let rendered = markdown::render(source, &markdown);
+        assert!(rendered.html.contains("<code class=\"language-rust\">"));
+        assert!(rendered.prism);
+        assert!(!markdown::render("没有代码", &markdown).prism);
+    }
+}
@@ -0,0 +1,505 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::assets::{Asset, percent_decode, percent_encode};
+use crate::functions::build::cache::ContentHash;
+use crate::functions::build::highlight::escape_html;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use lol_html::html_content::ContentType;
+use lol_html::{RewriteStrSettings, element, rewrite_str};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, process};
+
+/// 图片处理的选项，对应config.json中的images
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct ImageOptions {
+    pub enabled: bool,    // 是否生成不同尺寸的图片
+    pub widths: Vec<u32>, // 生成哪些宽度的图片，比原图宽的不会生成
+    pub webp: bool,       // 是否为PNG图片生成WebP格式的版本
+    pub quality: u8,      // 缩小后的JPEG图片的质量，1到100
+    pub sizes: String,    // <img>的sizes属性，告诉浏览器图片显示的宽度
+    pub lazy: bool,       // 文章中的图片是否延迟加载
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            enabled: true,
+            widths: vec![480, 960, 1440],
+            webp: true,
+            quality: 80,
+            sizes: String::from("(max-width: 700px) 100vw, 700px"),
+            lazy: true,
+        }
+    }
+}
+
+/// 图片的各个版本在输出目录中的位置，和文章引用的文件分开存放，不会覆盖用户自己的图片
+pub static VARIANTS_OUTPUT: &str = "public/_variants";
+
+// 可以处理的图片格式，GIF可能是动图，缩小以后会丢掉动画，所以只读取尺寸
+static RASTER_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "webp", "gif"];
+
+/// 一张图片的一个版本
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Variant {
+    pub target: String, // 相对于输出目录的路径
+    pub width: u32,
+    pub format: ImageFormat,
+}
+
+impl ImageOptions {
+    /// 检查选项是否合理，config_path用于报错
+    pub fn validate(&self, config_path: impl AsRef<Path>) -> Result<()> {
+        if !(1..=100).contains(&self.quality) || self.widths.contains(&0) {
+            return Err(PackpalError::config(
+                config_path,
+                format!(
+                    "images.quality应该在1到100之间，images.widths中的宽度应该大于0，现在是{}和{:?}",
+                    self.quality, self.widths
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// 图片需要生成的所有版本，不包括原图
+    ///
+    /// 比原图窄的每个宽度生成一张同格式的图片，PNG图片还会生成每个宽度（包括原图宽度）的WebP图片。
+    /// 生成的图片都放在[VARIANTS_OUTPUT]下，例如`public/assets/图1.png`的480px版本是
+    /// `public/_variants/assets/图1-480w.png`。
+    pub fn variants(&self, target: &str, width: u32) -> Vec<Variant> {
+        let Some(format) = resizable_format(target).filter(|_| self.enabled) else {
+            return Vec::new();
+        };
+        let mut widths = self
+            .widths
+            .iter()
+            .copied()
+            .filter(|w| *w < width)
+            .collect::<Vec<_>>();
+        widths.sort_unstable();
+        widths.dedup();
+
+        let relative = target.strip_prefix("public/").unwrap_or(target);
+        let (stem, _) = relative.rsplit_once('.').unwrap_or((relative, ""));
+        let stem = format!("{VARIANTS_OUTPUT}/{stem}");
+        let extension = target.rsplit('.').next().unwrap_or_default();
+        let mut variants = widths
+            .iter()
+            .map(|w| Variant {
+                target: format!("{stem}-{w}w.{extension}"),
+                width: *w,
+                format,
+            })
+            .collect::<Vec<_>>();
+        if self.webp && format == ImageFormat::Png {
+            variants.extend(widths.iter().map(|w| Variant {
+                target: format!("{stem}-{w}w.webp"),
+                width: *w,
+                format: ImageFormat::WebP,
+            }));
+            variants.push(Variant {
+                target: format!("{stem}.webp"),
+                width,
+                format: ImageFormat::WebP,
+            });
+        }
+        variants
+    }
+
+    /// 输入图片内容和选项的哈希值，用来判断上一次生成的版本还能不能用
+    pub fn fingerprint(&self, data: &[u8]) -> String {
+        ContentHash::new()
+            .update(serde_json::to_string(self).unwrap_or_default())
+            .update(data)
+            .finish()
+    }
+}
+
+// 可以缩小的图片格式
+fn resizable_format(target: &str) -> Option<ImageFormat> {
+    let extension = target.rsplit('.').next()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => Some(ImageFormat::Png),
+        "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+        "webp" => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+/// 读取图片的宽和高，不是图片或者无法读取时返回None
+pub fn image_size(path: impl AsRef<Path>) -> Option<(u32, u32)> {
+    let path = path.as_ref();
+    let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+    if !RASTER_EXTENSIONS.contains(&extension.as_str()) {
+        return None;
+    }
+    image::image_dimensions(path).ok()
+}
+
+/// 检查图片的各个版本会不会互相覆盖，或者覆盖文章引用的文件
+///
+/// 例如`a.png`和`a.webp`的480px版本都是`a-480w.webp`，这时报错并给出两个源文件的路径。
+pub fn check_collisions<'a>(
+    assets: impl IntoIterator<Item = &'a Asset>,
+    options: &ImageOptions,
+) -> Result<()> {
+    let assets = assets.into_iter().collect::<Vec<_>>();
+    let mut written = assets
+        .iter()
+        .map(|asset| (asset.target.clone(), &asset.source))
+        .collect::<BTreeMap<_, _>>();
+    for image in &assets {
+        let Some((width, _)) = image.size else {
+            continue;
+        };
+        for variant in options.variants(&image.target, width) {
+            if let Some(other) = written
+                .get(&variant.target)
+                .filter(|s| **s != &image.source)
+            {
+                return Err(PackpalError::render(
+                    &image.source,
+                    format!(
+                        "生成的{}会和{}冲突，请重命名其中一个文件",
+                        variant.target,
+                        other.display()
+                    ),
+                ));
+            }
+            written.insert(variant.target, &image.source);
+        }
+    }
+    Ok(())
+}
+
+/// 生成的图片在系统临时目录中的缓存，按图片内容和选项的哈希值存放
+///
+/// 重新创建输出目录（例如第一次build、packpal update）时，没有变化的图片直接从缓存中复制，不用重新编码。
+pub fn variant_cache_dir() -> PathBuf {
+    env::temp_dir().join("packpal-images")
+}
+
+/// 生成图片的各个版本，写入out_dir
+///
+/// 返回生成的所有文件（相对于输出目录），previous是上一次构建时这张图片的哈希值，
+/// 和这一次相同并且文件都还在时不重新生成。输出目录中没有的版本先到cache_dir中找，
+/// 找不到时才重新编码，并且存进cache_dir。
+pub fn generate(
+    image: &Asset,
+    options: &ImageOptions,
+    out_dir: &Path,
+    cache_dir: &Path,
+    fingerprint: &str,
+    previous: Option<&str>,
+) -> Result<Vec<String>> {
+    let Some((width, _)) = image.size else {
+        return Ok(Vec::new());
+    };
+    let variants = options.variants(&image.target, width);
+    let targets = variants
+        .iter()
+        .map(|v| v.target.clone())
+        .collect::<Vec<_>>();
+    if previous == Some(fingerprint) && targets.iter().all(|t| out_dir.join(t).is_file()) {
+        return Ok(targets);
+    }
+
+    let source = &image.source;
+    let mut original = None;
+    for variant in variants {
+        let path = out_dir.join(&variant.target);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| PackpalError::io(parent, e))?;
+        }
+        let extension = variant.format.extensions_str()[0];
+        let cached = cache_dir
+            .join(fingerprint)
+            .join(format!("{}w.{extension}", variant.width));
+        if let Ok(data) = fs::read(&cached) {
+            fs::write(&path, data).map_err(|e| PackpalError::io(&path, e))?;
+            continue;
+        }
+
+        // 所有版本都在缓存中时不需要读取原图
+        let original = match original {
+            Some(ref image) => image,
+            None => original.insert(
+                image::open(source)
+                    .map_err(|e| PackpalError::render(source, format!("无法读取图片：{e}")))?,
+            ),
+        };
+        let resized = if variant.width < original.width() {
+            let height = (original.height() as u64 * variant.width as u64 / original.width() as u64)
+                .max(1) as u32;
+            original.resize_exact(variant.width, height, FilterType::Lanczos3)
+        } else {
+            original.clone()
+        };
+        let data = encode(&resized, variant.format, options.quality)
+            .map_err(|e| PackpalError::render(&path, format!("无法生成图片：{e}")))?;
+        fs::write(&path, &data).map_err(|e| PackpalError::io(&path, e))?;
+        store(&cached, &data)?;
+    }
+    Ok(targets)
+}
+
+// 先写到临时文件再改名，几个线程或者几个packpal同时生成同一张图片时不会读到写了一半的缓存
+fn store(cached: &Path, data: &[u8]) -> Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let Some(dir) = cached.parent() else {
+        return Ok(());
+    };
+    fs::create_dir_all(dir).map_err(|e| PackpalError::io(dir, e))?;
+    let temp = dir.join(format!(
+        ".{}-{}.tmp",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&temp, data).map_err(|e| PackpalError::io(&temp, e))?;
+    fs::rename(&temp, cached).map_err(|e| PackpalError::io(cached, e))
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat, quality: u8) -> image::ImageResult<Vec<u8>> {
+    let mut data = Cursor::new(Vec::new());
+    match format {
+        // JPEG没有透明通道
+        ImageFormat::Jpeg => {
+            let encoder = JpegEncoder::new_with_quality(&mut data, quality);
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)?
+        }
+        // image只支持无损的WebP，所以只为PNG这样的无损图片生成WebP版本
+        ImageFormat::WebP => image.write_with_encoder(WebPEncoder::new_lossless(&mut data))?,
+        format => image.write_to(&mut data, format)?,
+    }
+    Ok(data.into_inner())
+}
+
+/// 给页面中引用了images中图片的`<img>`加上宽高、srcset和sizes，有WebP版本时用`<picture>`包起来
+///
+/// root是页面到网站根目录的相对路径，lazy表示是否加上`loading="lazy"`。
+/// 已经有srcset的`<img>`是手动写的，不做修改；已经有sizes的`<img>`保留原来的sizes。
+pub fn responsive(
+    html: &str,
+    root: &str,
+    images: &[Asset],
+    options: &ImageOptions,
+    lazy: bool,
+    page: impl AsRef<Path>,
+) -> Result<String> {
+    if !options.enabled || images.iter().all(|image| image.size.is_none()) {
+        return Ok(html.to_string());
+    }
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("img[src]:not([srcset])", |el| {
+                let src = unescape_html(&el.get_attribute("src").unwrap_or_default());
+                let Some(target) = src.strip_prefix(root) else {
+                    return Ok(());
+                };
+                let target = percent_decode(target);
+                let Some((image, (width, height))) = images
+                    .iter()
+                    .find(|image| image.target == target)
+                    .and_then(|image| image.size.map(|size| (image, size)))
+                else {
+                    return Ok(());
+                };
+
+                if !el.has_attribute("width") && !el.has_attribute("height") {
+                    el.set_attribute("width", &width.to_string())?;
+                    el.set_attribute("height", &height.to_string())?;
+                }
+                if lazy && !el.has_attribute("loading") {
+                    el.set_attribute("loading", "lazy")?;
+                    el.set_attribute("decoding", "async")?;
+                }
+
+                // 模板中可以自己写sizes，例如主页的头像只显示120px宽
+                let sizes = el
+                    .get_attribute("sizes")
+                    .map_or_else(|| options.sizes.clone(), |s| unescape_html(&s));
+                let variants = options.variants(&image.target, width);
+                let srcset = |format: ImageFormat| {
+                    variants
+                        .iter()
+                        .filter(|v| v.format == format)
+                        // srcset用空格分隔地址和宽度，地址中的空格必须转义
+                        .map(|v| format!("{root}{} {}w", percent_encode(&v.target), v.width))
+                        .collect::<Vec<_>>()
+                };
+                let original = resizable_format(&image.target);
+                let mut same_format = original.map(srcset).unwrap_or_default();
+                if !same_format.is_empty() {
+                    same_format.push(format!("{src} {width}w"));
+                    el.set_attribute("srcset", &same_format.join(", "))?;
+                    el.set_attribute("sizes", &sizes)?;
+                }
+                let webp = srcset(ImageFormat::WebP);
+                if original != Some(ImageFormat::WebP) && !webp.is_empty() {
+                    el.before(
+                        &format!(
+                            "<picture><source type=\"image/webp\" srcset=\"{}\" sizes=\"{}\">",
+                            escape_html(&webp.join(", ")),
+                            escape_html(&sizes)
+                        ),
+                        ContentType::Html,
+                    );
+                    el.after("</picture>", ContentType::Html);
+                }
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    )
+    .map_err(|e| PackpalError::render(page.as_ref(), e))
+}
+
+// 把HTML属性值中的字符实体还原，模板输出的地址中`/`会被转义成`&#x2f;`
+fn unescape_html(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').map(|end| (&rest[1..end], end));
+        let decoded = entity.and_then(|(name, end)| {
+            let c = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => match name.strip_prefix('#') {
+                    Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16)
+                        .ok()
+                        .and_then(char::from_u32),
+                    Some(dec) => dec.parse().ok().and_then(char::from_u32),
+                    None => None,
+                },
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::TEST_ROOT;
+    use image::RgbaImage;
+
+    #[test]
+    fn test_responsive_images() {
+        let dir = format!("{TEST_ROOT}test_responsive_images");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(format!("{dir}/build/public/assets")).unwrap();
+        let source = Path::new(&dir).join("区块 图.png");
+        RgbaImage::new(1000, 500).save(&source).unwrap();
+        let image = Asset {
+            size: image_size(&source),
+            source,
+            target: String::from("public/assets/区块 图.png"),
+        };
+        assert_eq!(image.size, Some((1000, 500)));
+
+        let options = ImageOptions::default();
+        let html =
+            r#"<p><img src="..&#x2f;public/assets/%E5%8C%BA%E5%9D%97%20图.png" alt="区块"></p>"#;
+        let html = responsive(
+            html,
+            "../",
+            std::slice::from_ref(&image),
+            &options,
+            true,
+            "a.html",
+        )
+        .unwrap();
+        assert!(html.starts_with(concat!(
+            r#"<p><picture><source type="image/webp" srcset="../public/_variants/assets/区块%20图-480w.webp 480w, "#,
+            r#"../public/_variants/assets/区块%20图-960w.webp 960w, ../public/_variants/assets/区块%20图.webp 1000w""#
+        )));
+        assert!(html.contains(concat!(
+            r#"srcset="../public/_variants/assets/区块%20图-480w.png 480w, "#,
+            r#"../public/_variants/assets/区块%20图-960w.png 960w, ../public/assets/%E5%8C%BA%E5%9D%97%20图.png 1000w""#
+        )));
+        assert!(html.contains(r#"width="1000" height="500" loading="lazy""#));
+        assert!(html.ends_with("</picture></p>"));
+
+        // 已经生成过并且图片没有变化时不重新生成
+        let out_dir = Path::new(&dir).join("build");
+        let cache_dir = Path::new(&dir).join("cache");
+        let fingerprint = options.fingerprint(&fs::read(&image.source).unwrap());
+        let files = generate(&image, &options, &out_dir, &cache_dir, &fingerprint, None).unwrap();
+        assert_eq!(files.len(), 5);
+        assert_eq!(
+            image::image_dimensions(out_dir.join("public/_variants/assets/区块 图-480w.png"))
+                .unwrap(),
+            (480, 240)
+        );
+        let webp = out_dir.join("public/_variants/assets/区块 图.webp");
+        fs::write(&webp, "旧的图片").unwrap();
+        let previous = Some(fingerprint.as_str());
+        generate(
+            &image,
+            &options,
+            &out_dir,
+            &cache_dir,
+            &fingerprint,
+            previous,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&webp).unwrap(), "旧的图片");
+
+        // 输出目录是新的时从缓存中复制，不重新编码
+        let cached = cache_dir.join(&fingerprint).join("1000w.webp");
+        fs::write(&cached, "缓存的图片").unwrap();
+        let new_out_dir = Path::new(&dir).join("new_build");
+        generate(
+            &image,
+            &options,
+            &new_out_dir,
+            &cache_dir,
+            &fingerprint,
+            None,
+        )
+        .unwrap();
+        let webp = new_out_dir.join("public/_variants/assets/区块 图.webp");
+        assert_eq!(fs::read_to_string(&webp).unwrap(), "缓存的图片");
+
+        // a.png和a.webp的480px版本都是a-480w.webp
+        let other = Asset {
+            size: Some((1000, 500)),
+            source: Path::new(&dir).join("区块 图.webp"),
+            target: String::from("public/assets/区块 图.webp"),
+        };
+        let err = check_collisions([&image, &other], &options).unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.contains("区块 图.png") && message.contains("区块 图.webp"),
+            "{message}"
+        );
+        assert!(check_collisions([&image, &image], &options).is_ok());
+    }
+}
@@ -1,7 +1,70 @@
+use crate::error::{PackpalError, Result};
 use crate::functions::build::Avatar;
+use crate::functions::build::cache::ContentHash;
+use crate::functions::build::dates::PostDate;
+use crate::functions::build::feed::{ATOM_FILE, RSS_FILE};
+use crate::functions::build::template::TemplateEngine;
+use minijinja::{Value, context};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+/// 主页模板的文件名
+pub static INDEX_TEMPLATE: &str = "index_template.html";
+/// 主页第2页及之后的页面在输出目录中的位置
+pub static PAGES_OUTPUT: &str = "page";
+
+/// 主页的选项，对应config.json中的index
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct IndexOptions {
+    pub page_size: usize, // 每一页展示多少篇文章，0表示所有文章都放在主页
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        IndexOptions { page_size: 10 }
+    }
+}
+
+impl IndexOptions {
+    /// 一共有多少页，没有文章时也有一页主页
+    pub fn total_pages(&self, posts: usize) -> usize {
+        match self.page_size {
+            0 => 1,
+            size => posts.div_ceil(size).max(1),
+        }
+    }
+
+    /// 第page页（从1开始）中的文章
+    pub fn page<'a>(&self, posts: &'a [PostInfo], page: usize) -> &'a [PostInfo] {
+        match self.page_size {
+            0 => posts,
+            size => {
+                let start = (page.saturating_sub(1) * size).min(posts.len());
+                &posts[start..(start + size).min(posts.len())]
+            }
+        }
+    }
+}
+
+/// 第page页相对于网站根目录的地址，第1页是主页，之后是`page/2/`、`page/3/`……
+pub fn page_url(page: usize) -> String {
+    match page {
+        0 | 1 => String::new(),
+        page => format!("{PAGES_OUTPUT}/{page}/"),
+    }
+}
+
+/// 第page页到网站根目录的相对路径
+pub fn page_root(page: usize) -> &'static str {
+    match page {
+        0 | 1 => "",
+        _ => "../../",
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct IndexInfo {
     pub site_name: String,
@@ -11,117 +74,183 @@ pub struct IndexInfo {
     pub date: String,
     pub(crate) avatar: Avatar,
     pub(crate) posts: Vec<PostInfo>,
+    pub feed: bool, // 是否生成了订阅源
 }
 
 impl IndexInfo {
-    pub fn new(
-        site_name: String,
-        motto: String,
-        github: String,
-        email: String,
-        date: String,
-        avatar: Avatar,
-        posts: Vec<PostInfo>,
-    ) -> Self {
-        Self {
-            site_name,
-            motto,
-            github,
-            email,
-            date,
-            avatar,
-            posts,
-        }
+    /// 主页输入的哈希值，包括站点信息、文章列表和模板
+    pub fn fingerprint(&self, template: &IndexTemplate) -> String {
+        let posts = serde_json::to_string(&self.posts).unwrap_or_default();
+        [
+            &self.site_name,
+            &self.motto,
+            &self.github,
+            &self.email,
+            &self.date,
+            &self.avatar.url,
+            &posts,
+            &self.feed.to_string(),
+        ]
+        .into_iter()
+        .fold(
+            ContentHash::new().update(template.0.fingerprint()),
+            |hash, part| hash.update(part),
+        )
+        .finish()
     }
 }
 
-#[derive(Debug, Clone)]
+/// 文章的摘要信息，用于在主页等列表页面中展示文章
+#[derive(Debug, Clone, Serialize)]
 pub struct PostInfo {
     pub title: String,
-    pub date: String,
+    pub date: String, // 按config.json中date_format格式化的日期，没有日期时是`----.--.--`
+    pub datetime: Option<PostDate>, // 发表时间，在模板中是RFC 3339格式的字符串
     pub url: String,
     pub excerpt: String,
+    pub description: Option<String>, // 元数据中的文章简介
+    pub tags: Vec<String>,
+    pub draft: bool, // 是否是草稿，只有使用--drafts生成时才会出现草稿
+}
+
+/// 文章从新到旧的顺序：没有日期的文章排在最后，时间相同的文章按标题和地址排列
+pub fn newest_first(a: &PostInfo, b: &PostInfo) -> Ordering {
+    b.datetime
+        .cmp(&a.datetime)
+        .then_with(|| a.title.cmp(&b.title))
+        .then_with(|| a.url.cmp(&b.url))
+}
+
+/// 按发表时间从新到旧排列文章
+pub fn sort_posts(posts: &mut [PostInfo]) {
+    posts.sort_by(newest_first);
 }
 
 #[derive(Debug, Clone)]
 pub struct HTMLIndexPage(String);
 
 impl HTMLIndexPage {
-    pub fn write_into_file(&self, path: PathBuf) {
-        fs::write(&path, self.0.as_bytes()).expect("[错误]写入索引页面文件失败，无效路径");
+    /// 用f改写页面内容，例如离线模式下替换外部资源
+    pub fn rewrite(self, f: impl FnOnce(&str) -> Result<String>) -> Result<Self> {
+        Ok(HTMLIndexPage(f(&self.0)?))
+    }
+
+    pub fn write_into_file(&self, path: PathBuf) -> Result<()> {
+        fs::write(&path, self.0.as_bytes()).map_err(|e| PackpalError::io(&path, e))
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct IndexTemplate(String);
+/// 主页模板，对应templates目录下的index_template.html
+#[derive(Clone)]
+pub struct IndexTemplate(TemplateEngine);
 
 impl IndexTemplate {
-    pub fn imports(path: impl AsRef<Path>) -> Self {
-        let content = fs::read_to_string(path).expect("[错误]读取索引模板文件失败，无效路径");
-        Self(content)
+    pub fn imports(engine: &TemplateEngine) -> Result<Self> {
+        engine.check(INDEX_TEMPLATE, &[])?;
+        Ok(Self(engine.clone()))
     }
 
-    pub fn render(&self, index: IndexInfo) -> HTMLIndexPage {
-        let mut content = self.0.clone();
-        content = content
-            .replace("{{site_name}}", &index.site_name)
-            .replace("{{motto}}", &index.motto)
-            .replace("{{avatar}}", &index.avatar.url)
-            .replace("{{github}}", &index.github)
-            .replace("{{date}}", &index.date);
-
-        let post_card_template = r###"
-            <article class="blog-card">
-                <div class="post-date">
-                    <i class="fa fa-calendar-o date-icon"></i>
-                    <time datetime="{{date}}">{{date}}</time>
-                </div>
-                <h3 class="post-title">
-                    <a href="{{url}}">{{title}}</a>
-                </h3>
-                <p class="post-excerpt">
-                    {{excerpt}}
-                </p>
-                <a href="{{url}}" class="read-more">
-                    阅读全文
-                    <i class="fa fa-long-arrow-right"></i>
-                </a>
-            </article>
-        "###;
-
-        let post_card_template_style_2 = r###"
-            <li>
-                <a href="{{url}}">
-                    <i class="fa fa-angle-right list-bullet"></i>
-                    <span class="list-date">{{date}}</span>
-                    <span>{{title}}</span>
-                </a>
-            </li>
-        "###;
+    /// 渲染主页的第page页（从1开始）
+    ///
+    /// 第2页及之后的页面在`page/<页码>/`下，文章、头像和订阅源的地址会加上到网站根目录的相对路径，
+    /// 这样直接使用`{{ post.url }}`的旧模板也能链接到正确的位置。
+    pub fn render(
+        &self,
+        index: &IndexInfo,
+        page: usize,
+        options: &IndexOptions,
+        output: impl AsRef<Path>,
+    ) -> Result<HTMLIndexPage> {
+        let output = output.as_ref();
+        let root = page_root(page);
+        let total_pages = options.total_pages(index.posts.len());
+        let posts = options
+            .page(&index.posts, page)
+            .iter()
+            .map(|post| PostInfo {
+                url: format!("{root}{}", post.url),
+                ..post.clone()
+            })
+            .collect::<Vec<_>>();
 
+        // 旧版主页模板使用{{post_cards}}和{{post_cards_style_2}}，
+        // 前三篇文章用post_card.html渲染成卡片，其余的用post_list_item.html渲染成列表
+        let variables = self.0.variables(INDEX_TEMPLATE)?;
         let mut post_cards = String::new();
         let mut post_cards_style_2 = String::new();
-
-        for (i, post) in index.posts.iter().enumerate() {
-            if i < 3 {
-                let post_card = post_card_template
-                    .replace("{{title}}", &post.title)
-                    .replace("{{date}}", &post.date)
-                    .replace("{{url}}", &post.url)
-                    .replace("{{excerpt}}", &post.excerpt);
-                post_cards.push_str(&post_card);
-            } else {
-                let post_card = post_card_template_style_2
-                    .replace("{{title}}", &post.title)
-                    .replace("{{date}}", &post.date)
-                    .replace("{{url}}", &post.url);
-                post_cards_style_2.push_str(&post_card);
+        if variables.contains("post_cards") || variables.contains("post_cards_style_2") {
+            for (i, post) in posts.iter().enumerate() {
+                let (partial, cards) = if i < 3 {
+                    ("partials/post_card.html", &mut post_cards)
+                } else {
+                    ("partials/post_list_item.html", &mut post_cards_style_2)
+                };
+                cards.push_str(&self.0.render(partial, context! { post }, output)?);
             }
         }
 
-        content = content.replace("{{post_cards}}", &post_cards);
-        content = content.replace("{{post_cards_style_2}}", &post_cards_style_2);
+        let ctx = context! {
+            site_name => index.site_name,
+            motto => index.motto,
+            avatar => format!("{root}{}", index.avatar.url),
+            github => index.github,
+            email => index.email,
+            date => index.date,
+            posts,
+            root,
+            page,
+            total_pages,
+            prev_page => (page > 1).then(|| format!("{root}{}", page_url(page - 1))),
+            next_page => (page < total_pages).then(|| format!("{root}{}", page_url(page + 1))),
+            rss => index.feed.then(|| format!("{root}{RSS_FILE}")),
+            atom => index.feed.then(|| format!("{root}{ATOM_FILE}")),
+            post_cards => Value::from_safe_string(post_cards),
+            post_cards_style_2 => Value::from_safe_string(post_cards_style_2),
+        };
+        Ok(HTMLIndexPage(self.0.render(INDEX_TEMPLATE, ctx, output)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_pages() {
+        let posts = (1..=7)
+            .map(|i| PostInfo {
+                title: format!("文章{i}"),
+                date: String::from("2024.09.07"),
+                datetime: None,
+                url: format!("articles/文章{i}.html"),
+                excerpt: String::new(),
+                description: None,
+                tags: Vec::new(),
+                draft: false,
+            })
+            .collect::<Vec<_>>();
+        let titles = |posts: &[PostInfo]| {
+            posts
+                .iter()
+                .map(|post| post.title.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let options = IndexOptions { page_size: 3 };
+        assert_eq!(options.total_pages(posts.len()), 3);
+        assert_eq!(titles(options.page(&posts, 1)), ["文章1", "文章2", "文章3"]);
+        assert_eq!(titles(options.page(&posts, 3)), ["文章7"]);
+        assert!(options.page(&posts, 4).is_empty());
+        assert_eq!(options.total_pages(0), 1);
+
+        let options = IndexOptions { page_size: 0 };
+        assert_eq!(options.total_pages(posts.len()), 1);
+        assert_eq!(options.page(&posts, 1).len(), 7);
 
-        HTMLIndexPage(content)
+        assert_eq!((page_url(1), page_root(1)), (String::new(), ""));
+        assert_eq!(
+            (page_url(3), page_root(3)),
+            (String::from("page/3/"), "../../")
+        );
     }
 }
@@ -0,0 +1,90 @@
+use crate::error::{PackpalError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// 清单文件的文件名，保存在输出目录的根目录下
+pub static MANIFEST_FILE: &str = ".packpal_manifest.json";
+
+/// 构建清单，记录一次构建写入输出目录的所有文件
+///
+/// 路径都是相对于输出目录的，用`/`分隔。`packpal clean`只会删除清单里记录的文件。
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct BuildManifest {
+    files: BTreeSet<String>,
+}
+
+impl BuildManifest {
+    /// 记录一个写入到out_dir中的文件，path可以是完整路径，也可以是相对out_dir的路径
+    pub fn record(&mut self, out_dir: impl AsRef<Path>, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        let relative = path.strip_prefix(out_dir.as_ref()).unwrap_or(path);
+        let relative = relative
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        self.files.insert(relative);
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(String::as_str)
+    }
+
+    pub fn contains(&self, file: &str) -> bool {
+        self.files.contains(file)
+    }
+
+    /// 读取out_dir下的清单，没有清单时返回None
+    pub fn load(out_dir: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = out_dir.as_ref().join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&path).map_err(|e| PackpalError::io(&path, e))?;
+        serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|e| PackpalError::metadata(&path, format!("构建清单格式错误：{e}")))
+    }
+
+    pub fn save(&self, out_dir: impl AsRef<Path>) -> Result<()> {
+        let path = out_dir.as_ref().join(MANIFEST_FILE);
+        let json =
+            serde_json::to_string_pretty(self).map_err(|e| PackpalError::metadata(&path, e))?;
+        fs::write(&path, json).map_err(|e| PackpalError::io(&path, e))
+    }
+}
+
+/// 把清单中的一条记录转换成out_dir下的路径
+///
+/// 只接受不含`..`、不是绝对路径的记录，防止被篡改的清单删除输出目录以外的文件
+pub fn resolve_entry(out_dir: impl AsRef<Path>, file: &str) -> Option<PathBuf> {
+    let relative = Path::new(file);
+    let is_safe = !file.is_empty()
+        && relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+    is_safe.then(|| out_dir.as_ref().join(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_relative_paths() {
+        let mut manifest = BuildManifest::default();
+        manifest.record("build/", "build/articles/比特币.html");
+        manifest.record("build/", Path::new("build").join("index.html"));
+        assert!(manifest.contains("articles/比特币.html"));
+        assert!(manifest.contains("index.html"));
+
+        assert!(resolve_entry("build", "articles/比特币.html").is_some());
+        assert!(resolve_entry("build", "../config.json").is_none());
+        assert!(resolve_entry("build", "/etc/passwd").is_none());
+    }
+}
@@ -0,0 +1,449 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::highlight::{self, HighlightOptions, escape_html};
+use crate::functions::build::math::{self, MathOptions};
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd, html};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Markdown渲染选项，对应config.json中的markdown
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct MarkdownOptions {
+    pub toc: TocOptions,
+    pub heading_anchors: bool, // 是否在标题后面加上指向这个标题的链接
+    pub highlight: HighlightOptions,
+    pub math: MathOptions,
+    pub excerpt: ExcerptOptions,
+}
+
+/// 文章摘要的选项
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct ExcerptOptions {
+    pub length: usize, // 没有`<!-- more -->`标记时，摘要最多包含正文的多少个字符
+}
+
+impl Default for ExcerptOptions {
+    fn default() -> Self {
+        ExcerptOptions { length: 100 }
+    }
+}
+
+/// 文章目录的选项，只有级别在min_depth和max_depth之间（包括两端）的标题会出现在目录中
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct TocOptions {
+    pub min_depth: u8,
+    pub max_depth: u8,
+}
+
+impl Default for TocOptions {
+    fn default() -> Self {
+        TocOptions {
+            min_depth: 1,
+            max_depth: 6,
+        }
+    }
+}
+
+impl MarkdownOptions {
+    /// 检查选项是否合理，config_path用于报错
+    pub fn validate(&self, config_path: impl AsRef<Path>) -> Result<()> {
+        let TocOptions {
+            min_depth,
+            max_depth,
+        } = self.toc;
+        if !(1..=6).contains(&min_depth) || !(1..=6).contains(&max_depth) || min_depth > max_depth {
+            return Err(PackpalError::config(
+                config_path,
+                format!(
+                    "markdown.toc的min_depth和max_depth应该在1到6之间，并且min_depth不大于max_depth，\
+                     现在是{min_depth}和{max_depth}"
+                ),
+            ));
+        }
+        if self.excerpt.length == 0 {
+            return Err(PackpalError::config(
+                config_path,
+                "markdown.excerpt.length应该大于0",
+            ));
+        }
+        if self.highlight.enabled && !highlight::has_theme(&self.highlight.theme) {
+            return Err(PackpalError::config(
+                config_path,
+                format!(
+                    "没有名为{}的代码高亮主题，可以使用的主题有：{}",
+                    self.highlight.theme,
+                    highlight::theme_names().join("、")
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 渲染好的Markdown
+#[derive(Debug, Clone)]
+pub struct RenderedMarkdown {
+    pub html: String,
+    pub toc: Vec<TocEntry>,
+    pub math: bool,  // 是否有需要在浏览器中用MathJax排版的公式
+    pub prism: bool, // 是否有需要在浏览器中用Prism高亮的代码块
+}
+
+/// 目录中的一项，children是它下面更深一级的标题
+#[derive(Debug, Clone, Serialize, Eq, PartialEq)]
+pub struct TocEntry {
+    pub level: u8,
+    pub id: String,
+    pub title: String,
+    pub children: Vec<TocEntry>,
+}
+
+// 正在渲染的标题
+struct Heading {
+    level: u8,
+    id: String,
+    title: String,
+}
+
+/// 把Markdown渲染成HTML，同时收集标题生成目录
+///
+/// 每个标题都会带上id：写了`{#id}`的使用指定的id，否则根据标题文字生成，见[`slugify`]。
+/// 同一篇文章中重复的id会依次加上`-1`、`-2`……
+pub fn render(source: &str, options: &MarkdownOptions) -> RenderedMarkdown {
+    let source = math::normalize_delimiters(source);
+    let mut events = Parser::new_ext(&source, Options::all()).collect::<Vec<_>>();
+
+    // 先记下文章中手动指定的id，生成的id要避开它们
+    let mut used_ids = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Start(Tag::Heading { id: Some(id), .. }) => Some(id.to_string()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    let mut headings = Vec::new();
+    let mut current: Option<(usize, Heading)> = None;
+    for index in 0..events.len() {
+        match &events[index] {
+            Event::Start(Tag::Heading { level, id, .. }) => {
+                let heading = Heading {
+                    level: *level as u8,
+                    id: id.as_deref().unwrap_or_default().to_string(),
+                    title: String::new(),
+                };
+                current = Some((index, heading));
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                let Some((start, mut heading)) = current.take() else {
+                    continue;
+                };
+                heading.title = heading.title.trim().to_string();
+                if heading.id.is_empty() {
+                    heading.id = unique_id(slugify(&heading.title), &mut used_ids);
+                    if let Event::Start(Tag::Heading { id, .. }) = &mut events[start] {
+                        *id = Some(CowStr::from(heading.id.clone()));
+                    }
+                }
+                headings.push(heading);
+            }
+            Event::Text(text) | Event::Code(text) | Event::InlineMath(text) => {
+                if let Some((_, heading)) = current.as_mut() {
+                    heading.title.push_str(text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // 在每个标题的结束标签前插入锚点链接，把代码块替换成高亮后的HTML，把公式替换成MathML
+    let mut ids = headings.iter().map(|heading| heading.id.as_str());
+    let mut code_block: Option<(String, String)> = None; // 正在读取的代码块的语言和代码
+    let mut output = Vec::with_capacity(events.len());
+    let mut has_math = false;
+    let has_code = events
+        .iter()
+        .any(|event| matches!(event, Event::Start(Tag::CodeBlock(_))));
+    for event in events {
+        match event {
+            Event::End(TagEnd::Heading(_)) if options.heading_anchors => {
+                if let Some(id) = ids.next() {
+                    output.push(Event::InlineHtml(CowStr::from(format!(
+                        "<a class=\"heading-anchor\" href=\"#{}\" aria-hidden=\"true\">#</a>",
+                        escape_html(id)
+                    ))));
+                }
+                output.push(event);
+            }
+            Event::Start(Tag::CodeBlock(kind)) if options.highlight.enabled => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => info.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code_block = Some((lang, String::new()));
+            }
+            Event::Text(text) if code_block.is_some() => {
+                if let Some((_, code)) = code_block.as_mut() {
+                    code.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) if code_block.is_some() => {
+                if let Some((lang, code)) = code_block.take() {
+                    let html = highlight::highlight(&code, &lang, &options.highlight);
+                    output.push(Event::Html(CowStr::from(html)));
+                }
+            }
+            Event::InlineMath(tex) => {
+                has_math = true;
+                output.push(Event::InlineHtml(CowStr::from(math::render(
+                    &tex,
+                    false,
+                    &options.math,
+                ))));
+            }
+            Event::DisplayMath(tex) => {
+                has_math = true;
+                output.push(Event::Html(CowStr::from(math::render(
+                    &tex,
+                    true,
+                    &options.math,
+                ))));
+            }
+            event => output.push(event),
+        }
+    }
+    let mut html = String::new();
+    html::push_html(&mut html, output.into_iter());
+
+    let headings = headings
+        .into_iter()
+        .filter(|heading| (options.toc.min_depth..=options.toc.max_depth).contains(&heading.level))
+        .collect::<Vec<_>>();
+    RenderedMarkdown {
+        html,
+        toc: nest(&headings),
+        math: has_math && !options.math.enabled,
+        prism: has_code && !options.highlight.enabled,
+    }
+}
+
+/// 文章的纯文本摘要，去掉了Markdown和HTML标记，连续的空白合并成一个空格
+///
+/// 正文中有`<!-- more -->`时，摘要是标记之前的全部内容；
+/// 否则取正文的前`excerpt.length`个字符，截断时在末尾加上`…`。代码块和图片不会出现在摘要中。
+pub fn excerpt(source: &str, options: &ExcerptOptions) -> String {
+    let mut text = String::new();
+    let mut more = false;
+    let mut skipping = 0; // 正在跳过的标题、代码块和图片的层数
+    let source = math::normalize_delimiters(source);
+    for event in Parser::new_ext(&source, Options::all()) {
+        match event {
+            Event::Html(html) | Event::InlineHtml(html) if is_more_marker(&html) => {
+                more = true;
+                break;
+            }
+            // 文章开头的标题通常就是文章的标题，摘要中不再重复；小节的标题也不放进摘要
+            Event::Start(Tag::Heading { .. } | Tag::CodeBlock(_) | Tag::Image { .. }) => {
+                skipping += 1
+            }
+            Event::End(TagEnd::Heading(_) | TagEnd::CodeBlock | TagEnd::Image) => skipping -= 1,
+            _ if skipping > 0 => {}
+            Event::Text(t) | Event::Code(t) | Event::InlineMath(t) | Event::DisplayMath(t) => {
+                text.push_str(&t)
+            }
+            // 换行和段落、列表项等块的结尾都当成空白，避免前后两段的文字连在一起
+            Event::SoftBreak
+            | Event::HardBreak
+            | Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Item
+                | TagEnd::BlockQuote(_)
+                | TagEnd::TableCell
+                | TagEnd::FootnoteDefinition,
+            ) => text.push(' '),
+            _ => {}
+        }
+    }
+
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if more || text.chars().count() <= options.length {
+        return text;
+    }
+    let mut excerpt = text.chars().take(options.length).collect::<String>();
+    excerpt.truncate(excerpt.trim_end().len());
+    excerpt.push('…');
+    excerpt
+}
+
+// <!-- more -->标记，不区分大小写，注释中的空白可有可无
+fn is_more_marker(html: &str) -> bool {
+    let marker = html
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    marker.eq_ignore_ascii_case("<!--more-->")
+}
+
+/// 根据标题文字生成id
+///
+/// 保留各种文字（包括中文）和数字，英文字母转成小写，空白和其他符号都换成`-`，
+/// 这样id在URL中不需要转义特殊符号。标题中没有文字时使用`section`。
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        String::from("section")
+    } else {
+        slug.to_string()
+    }
+}
+
+// 在slug后面加上数字，直到它和已经用过的id都不一样
+fn unique_id(slug: String, used: &mut HashSet<String>) -> String {
+    if used.insert(slug.clone()) {
+        return slug;
+    }
+    (1..)
+        .map(|n| format!("{slug}-{n}"))
+        .find(|id| used.insert(id.clone()))
+        .unwrap_or(slug)
+}
+
+// 把按顺序排列的标题组织成树：一个标题后面、下一个同级或更高级标题之前的标题都是它的子项
+fn nest(headings: &[Heading]) -> Vec<TocEntry> {
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < headings.len() {
+        let heading = &headings[i];
+        let end = headings[i + 1..]
+            .iter()
+            .position(|next| next.level <= heading.level)
+            .map_or(headings.len(), |position| i + 1 + position);
+        entries.push(TocEntry {
+            level: heading.level,
+            id: heading.id.clone(),
+            title: heading.title.trim().to_string(),
+            children: nest(&headings[i + 1..end]),
+        });
+        i = end;
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toc() {
+        let source = "# 比特币\n\n## 背景\n\n### `区块`\n\n## 交易 {#tx}\n\n#### 签名\n\n# 总结\n";
+        let rendered = render(source, &MarkdownOptions::default());
+        assert!(rendered.html.contains("<h2 id=\"背景\">背景</h2>"));
+        assert!(rendered.html.contains("<h2 id=\"tx\">交易</h2>"));
+
+        let toc = rendered.toc;
+        assert_eq!(toc.len(), 2);
+        assert_eq!(toc[0].title, "比特币");
+        let children = &toc[0].children;
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].children[0].title, "区块");
+        assert_eq!(children[1].id, "tx");
+        assert_eq!(children[1].children[0].level, 4);
+        assert_eq!(toc[1].id, "总结");
+
+        // 只保留二级和三级标题
+        let options = MarkdownOptions {
+            toc: TocOptions {
+                min_depth: 2,
+                max_depth: 3,
+            },
+            ..MarkdownOptions::default()
+        };
+        let toc = render(source, &options).toc;
+        let titles = toc
+            .iter()
+            .map(|entry| entry.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["背景", "交易"]);
+        assert_eq!(toc[0].children[0].title, "区块");
+        assert!(toc[1].children.is_empty());
+
+        let invalid = MarkdownOptions {
+            toc: TocOptions {
+                min_depth: 4,
+                max_depth: 2,
+            },
+            ..MarkdownOptions::default()
+        };
+        assert!(invalid.validate("config.json").is_err());
+    }
+
+    #[test]
+    fn test_heading_anchors() {
+        assert_eq!(
+            slugify("比特币：一种点对点的电子现金系统"),
+            "比特币-一种点对点的电子现金系统"
+        );
+        assert_eq!(slugify("  Hello, World! 你好 "), "hello-world-你好");
+        assert_eq!(slugify("?!"), "section");
+
+        let source = "## 背景\n\n## 背景\n\n## 其他 {#背景-1}\n\n## 背景\n";
+        let ids = render(source, &MarkdownOptions::default())
+            .toc
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect::<Vec<_>>();
+        // 手动指定的id优先，生成的id跳过已经用过的
+        assert_eq!(ids, ["背景", "背景-2", "背景-1", "背景-3"]);
+
+        let options = MarkdownOptions {
+            heading_anchors: true,
+            ..MarkdownOptions::default()
+        };
+        let html = render("## A & B {#a&b}\n\n正文\n", &options).html;
+        assert!(html.contains(
+            "A &amp; B<a class=\"heading-anchor\" href=\"#a&amp;b\" aria-hidden=\"true\">#</a></h2>"
+        ));
+        assert!(
+            !render("## 标题\n", &MarkdownOptions::default())
+                .html
+                .contains("heading-anchor")
+        );
+    }
+
+    #[test]
+    fn test_excerpt() {
+        let options = ExcerptOptions { length: 12 };
+        let source = "# 比特币\n\n**比特币**是一种[数字货币](https://bitcoin.org)。\n\n![封面](a.png)\n\n```rust\nfn main() {}\n```\n\n第二段";
+        assert_eq!(excerpt(source, &options), "比特币是一种数字货币。…");
+        // 标题不出现在摘要中，包括Setext风格的标题
+        let source = "# Title\n\nFirst paragraph.\n\n## Section\n\nSecond\n===\n\nmore";
+        assert_eq!(
+            excerpt(source, &ExcerptOptions { length: 100 }),
+            "First paragraph. more"
+        );
+
+        // 多字节字符不会被截断在中间
+        assert_eq!(
+            excerpt("中文😀abc", &ExcerptOptions { length: 3 }),
+            "中文😀…"
+        );
+        assert_eq!(excerpt("短", &options), "短");
+
+        let source = "第一段`code`\n\n<!-- More -->\n\n这一段很长很长很长很长很长很长很长";
+        assert_eq!(excerpt(source, &options), "第一段code");
+        // 代码块中的标记不算
+        let source = "```\n<!-- more -->\n```\n\n正文";
+        assert_eq!(excerpt(source, &options), "正文");
+    }
+}
@@ -0,0 +1,214 @@
+use crate::functions::build::highlight::escape_html;
+use katex::{Opts, OutputType};
+use serde::{Deserialize, Serialize};
+
+/// 数学公式的选项，对应config.json中的markdown.math
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct MathOptions {
+    pub enabled: bool, // 是否在生成时把公式渲染成MathML
+}
+
+impl Default for MathOptions {
+    fn default() -> Self {
+        MathOptions { enabled: true }
+    }
+}
+
+/// 渲染一个公式，display为true时是单独成行的公式（`$$...$$`），否则是行内公式（`$...$`）
+///
+/// 启用时用KaTeX渲染成MathML，浏览器可以直接显示，不需要任何脚本和样式表；
+/// 公式有语法错误时KaTeX会把原文标红输出，不会中断构建。
+/// 不启用时输出带有`\(...\)`或`\[...\]`分隔符的原文，交给页面中的MathJax排版。
+pub fn render(tex: &str, display: bool, options: &MathOptions) -> String {
+    if options.enabled {
+        let opts = Opts::builder()
+            .display_mode(display)
+            .output_type(OutputType::Mathml)
+            .throw_on_error(false)
+            .build()
+            .ok();
+        if let Some(html) = opts.and_then(|opts| katex::render_with_opts(tex, &opts).ok()) {
+            return html;
+        }
+    }
+    let tex = escape_html(tex);
+    if display {
+        format!("<span class=\"math math-display\">\\[{tex}\\]</span>")
+    } else {
+        format!("<span class=\"math math-inline\">\\({tex}\\)</span>")
+    }
+}
+
+/// 把`$ a^2 $`这样内侧有空白的行内公式改写成`$a^2$`
+///
+/// pulldown-cmark要求行内公式的`$`内侧不能是空白，否则当成普通文字，但很多编辑器（例如语雀）导出的
+/// Markdown都带着这些空白。代码块和行内代码中的`$`保持原样，`$$`和`\$`也不处理。
+pub fn normalize_delimiters(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut paragraph = String::new();
+    let mut fence: Option<String> = None; // 正在读取的代码块的围栏，例如```
+    for line in source.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let marker = ["```", "~~~"]
+            .into_iter()
+            .find(|marker| trimmed.starts_with(marker) && line.len() - trimmed.len() < 4);
+        match (&fence, marker) {
+            (Some(open), _) => {
+                if trimmed.trim_end().starts_with(open.as_str())
+                    && trimmed.trim_end().chars().all(|c| open.starts_with(c))
+                {
+                    fence = None;
+                }
+                output.push_str(line);
+            }
+            (None, Some(marker)) => {
+                output.push_str(&normalize_paragraph(&paragraph));
+                paragraph.clear();
+                let c = marker.chars().next().unwrap_or('`');
+                fence = Some(trimmed.chars().take_while(|&x| x == c).collect());
+                output.push_str(line);
+            }
+            // 公式不会跨越空行，遇到空行时处理前面的段落
+            (None, None) if trimmed.is_empty() => {
+                output.push_str(&normalize_paragraph(&paragraph));
+                paragraph.clear();
+                output.push_str(line);
+            }
+            (None, None) => paragraph.push_str(line),
+        }
+    }
+    output.push_str(&normalize_paragraph(&paragraph));
+    output
+}
+
+// 处理一个段落中的`$ ... $`，跳过行内代码和写法正确的公式
+fn normalize_paragraph(paragraph: &str) -> String {
+    let chars = paragraph.chars().collect::<Vec<_>>();
+    let mut output = String::with_capacity(paragraph.len());
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                output.extend(&chars[i..(i + 2).min(chars.len())]);
+                i += 2;
+            }
+            '`' => {
+                let run = chars[i..].iter().take_while(|&&c| c == '`').count();
+                let end = find_backticks(&chars, i + run, run).unwrap_or(i + run);
+                output.extend(&chars[i..end]);
+                i = end;
+            }
+            '$' if chars.get(i + 1) == Some(&'$') => {
+                output.push_str("$$");
+                i += 2;
+            }
+            '$' => {
+                let spaced = chars.get(i + 1).is_some_and(|c| c.is_whitespace());
+                let close = (i + 1..chars.len()).find(|&j| {
+                    chars[j] == '$'
+                        && chars[j - 1] != '\\'
+                        && chars.get(j + 1) != Some(&'$')
+                        && chars[j - 1].is_whitespace() == spaced
+                });
+                match close {
+                    Some(j) if spaced => {
+                        let tex = chars[i + 1..j].iter().collect::<String>();
+                        if tex.trim().is_empty() {
+                            output.extend(&chars[i..=j]);
+                        } else {
+                            output.push('$');
+                            output.push_str(tex.trim());
+                            output.push('$');
+                        }
+                        i = j + 1;
+                    }
+                    // 写法正确的公式原样保留，避免把它的结束符当成下一个公式的开始
+                    Some(j) => {
+                        output.extend(&chars[i..=j]);
+                        i = j + 1;
+                    }
+                    None => {
+                        output.push('$');
+                        i += 1;
+                    }
+                }
+            }
+            c => {
+                output.push(c);
+                i += 1;
+            }
+        }
+    }
+    output
+}
+
+// 从start开始找长度正好是run的一串反引号，返回这串反引号之后的位置
+fn find_backticks(chars: &[char], start: usize, run: usize) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        if chars[i] == '`' {
+            let length = chars[i..].iter().take_while(|&&c| c == '`').count();
+            if length == run {
+                return Some(i + length);
+            }
+            i += length;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::build::markdown::{self, MarkdownOptions};
+
+    #[test]
+    fn test_math() {
+        let source = "欧拉定理：$a^{\\phi(n)} \\equiv 1$\n\n$$\nC = M^e\\ mod\\ n\n$$\n";
+        let rendered = markdown::render(source, &MarkdownOptions::default());
+        assert!(!rendered.math);
+        assert!(
+            rendered
+                .html
+                .contains("<math xmlns=\"http://www.w3.org/1998/Math/MathML\">")
+        );
+        assert!(
+            rendered
+                .html
+                .contains("<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"block\">")
+        );
+        assert!(!rendered.html.contains('$'));
+
+        // 有语法错误的公式原样标红输出
+        assert!(render("\\frac{1}{", false, &MathOptions::default()).contains("katex-error"));
+
+        let options = MarkdownOptions {
+            math: MathOptions { enabled: false },
+            ..MarkdownOptions::default()
+        };
+        let rendered = markdown::render(source, &options);
+        assert!(rendered.math);
+        assert!(rendered.html.contains("\\(a^{\\phi(n)} \\equiv 1\\)"));
+        assert!(rendered.html.contains("\\[\nC = M^e\\ mod\\ n\n\\]"));
+        assert!(!markdown::render("没有公式", &options).math);
+
+        // 语雀等编辑器导出的公式在`$`内侧带着空白，也要识别成公式
+        let source = "$ 如果正整数a和n互质，则有：a^{\\phi(n)} \\equiv 1(mod\\ n) \\newline\n\
+                      即 a^{\\phi(n)+1} \\equiv a(mod\\ n) $\n\n\
+                      其中$ \\phi(n) $是欧拉函数，`$ x $`和$y$保持原样\n\n\
+                      ```sh\n$ echo $ HOME\n```\n";
+        let rendered = markdown::render(source, &options);
+        assert!(rendered.math);
+        assert!(rendered.html.contains("\\(如果正整数a和n互质"));
+        assert!(rendered.html.contains("\\(\\phi(n)\\)"));
+        assert!(rendered.html.contains("<code>$ x $</code>"));
+        assert!(rendered.html.contains("\\(y\\)"));
+        assert!(normalize_delimiters(source).ends_with("```sh\n$ echo $ HOME\n```\n"));
+        let rendered = markdown::render(source, &MarkdownOptions::default());
+        assert!(!rendered.math);
+        assert_eq!(rendered.html.matches("<math ").count(), 3);
+    }
+}
@@ -0,0 +1,356 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::manifest::BuildManifest;
+use lol_html::html_content::{ContentType, Element};
+use lol_html::{HandlerResult, RewriteStrSettings, element, rewrite_str, text};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 本地资源在输出目录中的位置
+pub static VENDOR_OUTPUT: &str = "public/vendor";
+
+/// 离线模式的选项，对应config.json中的offline
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct OfflineConfig {
+    pub enabled: bool, // 是否以离线模式生成，也可以用packpal build --offline临时开启
+    pub vendor_dir: String, // 外部资源的本地副本所在的目录，相对于项目根目录
+}
+
+impl Default for OfflineConfig {
+    fn default() -> Self {
+        OfflineConfig {
+            enabled: false,
+            vendor_dir: String::from("./vendor/"),
+        }
+    }
+}
+
+// 这些rel的<link>会让浏览器加载href指向的资源，其余的（例如canonical）只是链接
+static RESOURCE_RELS: [&str; 7] = [
+    "stylesheet",
+    "icon",
+    "apple-touch-icon",
+    "manifest",
+    "preload",
+    "modulepreload",
+    "prefetch",
+];
+
+/// 外部资源的本地副本
+///
+/// 本地副本按照URL的域名和路径存放，例如
+/// `https://cdn.jsdelivr.net/npm/mathjax@3/es5/tex-svg.js`的本地副本是
+/// `vendor/cdn.jsdelivr.net/npm/mathjax@3/es5/tex-svg.js`，和`wget -x`下载的目录结构相同，
+/// 所以样式表中用相对路径引用的字体等文件也能正常加载。
+#[derive(Debug, Clone)]
+pub struct Vendor {
+    dir: PathBuf,
+}
+
+impl Vendor {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Vendor {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// 把页面中引用的外部资源替换成本地副本
+    ///
+    /// root是页面到网站根目录的相对路径，例如文章页面是`../`。
+    /// 有外部资源没有本地副本时返回错误，列出所有缺少的资源。
+    pub fn localize(&self, html: &str, root: &str, page: impl AsRef<Path>) -> Result<String> {
+        let missing = RefCell::new(BTreeSet::new());
+        let local = |url: &str| self.local_url(url, root, &mut missing.borrow_mut());
+        let mut style = String::new();
+
+        let rewritten = rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("[src]", |el| rewrite_attribute(el, "src", &local)),
+                    element!("[poster]", |el| rewrite_attribute(el, "poster", &local)),
+                    element!("object[data]", |el| rewrite_attribute(el, "data", &local)),
+                    element!("[srcset]", |el| {
+                        rewrite_attribute(el, "srcset", &|srcset| rewrite_srcset(srcset, &local))
+                    }),
+                    element!("[style]", |el| {
+                        rewrite_attribute(el, "style", &|css| rewrite_css(css, &local))
+                    }),
+                    element!("link[href]", |el| {
+                        let rel = el.get_attribute("rel").unwrap_or_default().to_lowercase();
+                        if rel
+                            .split_whitespace()
+                            .any(|rel| RESOURCE_RELS.contains(&rel))
+                        {
+                            rewrite_attribute(el, "href", &local)?;
+                        }
+                        Ok(())
+                    }),
+                    // <style>中的内容可能被分成好几段，拼接完整以后再改写
+                    text!("style", |chunk| {
+                        style.push_str(chunk.as_str());
+                        if chunk.last_in_text_node() {
+                            let css = rewrite_css(&std::mem::take(&mut style), &local);
+                            chunk.replace(&css, ContentType::Html);
+                        } else {
+                            chunk.remove();
+                        }
+                        Ok(())
+                    }),
+                ],
+                ..RewriteStrSettings::new()
+            },
+        )
+        .map_err(|e| PackpalError::render(page.as_ref(), e))?;
+
+        let missing = missing.into_inner();
+        if !missing.is_empty() {
+            return Err(PackpalError::render(
+                page.as_ref(),
+                self.missing_message(missing),
+            ));
+        }
+        Ok(rewritten)
+    }
+
+    /// 把所有本地副本复制到输出目录的public/vendor下，并记录到构建清单中
+    pub fn copy_into(&self, out_dir: impl AsRef<Path>, manifest: &mut BuildManifest) -> Result<()> {
+        let out_dir = out_dir.as_ref();
+        if !self.dir.is_dir() {
+            return Ok(());
+        }
+        let mut files = Vec::new();
+        collect_files(&self.dir, &mut files)?;
+        for file in files {
+            let relative = file.strip_prefix(&self.dir).unwrap_or(&file);
+            let target = out_dir.join(VENDOR_OUTPUT).join(relative);
+            let data = fs::read(&file).map_err(|e| PackpalError::io(&file, e))?;
+
+            // 本地的样式表还可以引用别的外部资源，这些资源也必须有本地副本
+            if file.extension().is_some_and(|ext| ext == "css") {
+                let external = RefCell::new(BTreeSet::new());
+                rewrite_css(&String::from_utf8_lossy(&data), &|url: &str| {
+                    if is_external(url) {
+                        external.borrow_mut().insert(url.to_string());
+                    }
+                    url.to_string()
+                });
+                let external = external.into_inner();
+                if !external.is_empty() {
+                    return Err(PackpalError::render(
+                        &file,
+                        format!(
+                            "离线模式下样式表不能引用外部资源，请把以下资源改成相对路径：\n{}",
+                            external.into_iter().collect::<Vec<_>>().join("\n")
+                        ),
+                    ));
+                }
+            }
+
+            if !fs::read(&target).is_ok_and(|old| old == data) {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).map_err(|e| PackpalError::io(parent, e))?;
+                }
+                fs::write(&target, data).map_err(|e| PackpalError::io(&target, e))?;
+            }
+            manifest.record(out_dir, target);
+        }
+        Ok(())
+    }
+
+    // 外部资源的本地地址，不是外部资源时原样返回，没有本地副本时记录到missing中
+    fn local_url(&self, url: &str, root: &str, missing: &mut BTreeSet<String>) -> String {
+        let Some(relative) = vendor_path(url) else {
+            return url.to_string();
+        };
+        // 查找本地副本时去掉URL中的查询参数和锚点，改写后的地址中保留它们
+        let file = relative.split(['?', '#']).next().unwrap_or_default();
+        if file.is_empty() || file.ends_with('/') || !self.dir.join(file).is_file() {
+            missing.insert(url.to_string());
+            return url.to_string();
+        }
+        format!("{root}{VENDOR_OUTPUT}/{relative}")
+    }
+
+    fn missing_message(&self, missing: BTreeSet<String>) -> String {
+        let list = missing
+            .iter()
+            .map(|url| {
+                let file = vendor_path(url).unwrap_or_default();
+                let file = file.split(['?', '#']).next().unwrap_or_default();
+                format!("{url} -> {}", self.dir.join(file).display())
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(
+            "离线模式下页面引用的外部资源必须有本地副本，请把以下资源下载到对应的位置：\n{list}"
+        )
+    }
+}
+
+/// 用f改写元素的name属性
+pub fn rewrite_attribute(
+    el: &mut Element,
+    name: &str,
+    f: &dyn Fn(&str) -> String,
+) -> HandlerResult {
+    if let Some(value) = el.get_attribute(name) {
+        let rewritten = f(&value);
+        if rewritten != value {
+            el.set_attribute(name, &rewritten)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_external(url: &str) -> bool {
+    vendor_path(url).is_some()
+}
+
+// 外部资源在vendor目录中的相对路径，即去掉协议以后的部分；不是外部资源时返回None
+fn vendor_path(url: &str) -> Option<&str> {
+    let url = url.trim();
+    let lower = url.to_ascii_lowercase();
+    ["https://", "http://", "//"]
+        .iter()
+        .find(|scheme| lower.starts_with(*scheme))
+        .map(|scheme| &url[scheme.len()..])
+}
+
+// 改写srcset中的每一个地址，例如"a.png 1x, b.png 2x"
+fn rewrite_srcset(srcset: &str, f: &dyn Fn(&str) -> String) -> String {
+    srcset
+        .split(',')
+        .map(|candidate| {
+            let candidate = candidate.trim();
+            match candidate.split_once(char::is_whitespace) {
+                Some((url, descriptor)) => format!("{} {}", f(url), descriptor.trim()),
+                None => f(candidate),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// 改写CSS中url(...)和@import "..."引用的地址
+fn rewrite_css(css: &str, f: &dyn Fn(&str) -> String) -> String {
+    let mut output = String::with_capacity(css.len());
+    let mut rest = css;
+    loop {
+        let lower = rest.to_ascii_lowercase();
+        let next = [lower.find("url("), lower.find("@import")]
+            .into_iter()
+            .flatten()
+            .min();
+        let Some(start) = next else {
+            output.push_str(rest);
+            return output;
+        };
+        let (before, after) = rest.split_at(start);
+        output.push_str(before);
+
+        let (prefix, body) = if lower[start..].starts_with("url(") {
+            after.split_at(4)
+        } else {
+            let spaces = after[7..].len() - after[7..].trim_start().len();
+            after.split_at(7 + spaces)
+        };
+        output.push_str(prefix);
+
+        // 地址可以用引号括起来，url()中也可以不用引号
+        let quote = body.chars().next().filter(|c| *c == '"' || *c == '\'');
+        let (open, end) = match quote {
+            Some(q) => (1, body[1..].find(q).map(|i| i + 1)),
+            None if prefix.ends_with('(') => (0, body.find(')')),
+            None => {
+                rest = body;
+                continue;
+            }
+        };
+        let Some(end) = end else {
+            output.push_str(body);
+            return output;
+        };
+        output.push_str(&body[..open]);
+        output.push_str(&f(&body[open..end]));
+        rest = &body[end..];
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).map_err(|e| PackpalError::io(dir, e))? {
+        let path = entry.map_err(|e| PackpalError::io(dir, e))?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::TEST_ROOT;
+
+    #[test]
+    fn test_localize() {
+        let dir = format!("{TEST_ROOT}test_localize");
+        let _ = fs::remove_dir_all(&dir);
+        let vendor = Vendor::new(format!("{dir}/vendor"));
+        fs::create_dir_all(format!("{dir}/vendor/cdn.example.com/fa/css")).unwrap();
+        fs::create_dir_all(format!("{dir}/vendor/cdn.example.com/fa/fonts")).unwrap();
+        fs::write(
+            format!("{dir}/vendor/cdn.example.com/fa/css/fa.css"),
+            "@font-face { src: url('../fonts/fa.woff2?v=4.7.0'); }",
+        )
+        .unwrap();
+        fs::write(
+            format!("{dir}/vendor/cdn.example.com/fa/fonts/fa.woff2"),
+            "",
+        )
+        .unwrap();
+        fs::write(format!("{dir}/vendor/cdn.example.com/a.js"), "").unwrap();
+
+        let html = r#"<head>
+<link rel="stylesheet" href="https://cdn.example.com/fa/css/fa.css">
+<link rel="canonical" href="https://blog.example.com/">
+<script src="//cdn.example.com/a.js?v=1"></script>
+<style>body { background: url("https://cdn.example.com/a.js"); }</style>
+</head>
+<a href="https://github.com/">GitHub</a><img src="../public/avatar.png">"#;
+        let localized = vendor.localize(html, "../", "index.html").unwrap();
+        assert!(localized.contains(r#"href="../public/vendor/cdn.example.com/fa/css/fa.css""#));
+        assert!(localized.contains(r#"src="../public/vendor/cdn.example.com/a.js?v=1""#));
+        assert!(localized.contains(r#"url("../public/vendor/cdn.example.com/a.js")"#));
+        // 普通链接和本地资源不改写
+        assert!(localized.contains(r#"href="https://blog.example.com/""#));
+        assert!(localized.contains(r#"href="https://github.com/""#));
+        assert!(localized.contains(r#"src="../public/avatar.png""#));
+
+        // 没有本地副本的外部资源会让构建失败，错误信息中列出所有缺少的资源
+        let html =
+            r#"<img srcset="https://img.example.com/a.png 1x, https://img.example.com/b.png 2x">"#;
+        let err = vendor
+            .localize(html, "", "index.html")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("https://img.example.com/a.png"));
+        assert!(err.contains("https://img.example.com/b.png"));
+
+        let out_dir = PathBuf::from(format!("{dir}/build"));
+        let mut manifest = BuildManifest::default();
+        vendor.copy_into(&out_dir, &mut manifest).unwrap();
+        assert!(
+            out_dir
+                .join(VENDOR_OUTPUT)
+                .join("cdn.example.com/fa/fonts/fa.woff2")
+                .exists()
+        );
+        assert!(manifest.contains("public/vendor/cdn.example.com/fa/css/fa.css"));
+    }
+}
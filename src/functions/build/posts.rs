@@ -1,10 +1,22 @@
-use pulldown_cmark::{html, Options, Parser};
+use crate::error::{PackpalError, Result};
+use crate::functions::build::assets::{Asset, link_assets};
+use crate::functions::build::cache::ContentHash;
+use crate::functions::build::dates::{MISSING_DATE, PostDate};
+use crate::functions::build::front_matter::split_front_matter;
+use crate::functions::build::highlight::HIGHLIGHT_CSS;
+use crate::functions::build::index::PostInfo;
+use crate::functions::build::markdown::{self, MarkdownOptions};
+use crate::functions::build::template::TemplateEngine;
+use chrono_tz::Tz;
+use minijinja::{Value, context};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
-use crate::functions::build::index::{IndexInfo, PostInfo};
-use crate::functions::build::posts;
+
+/// 文章模板的文件名
+pub static POST_TEMPLATE: &str = "posts_template.html";
 
 #[derive(Clone, Debug, Default)]
 pub enum SourceType {
@@ -17,36 +29,74 @@ pub enum SourceType {
 pub struct HTMLPost {
     name: String,
     content: String,
+    body: String, // 文章正文的HTML，不含模板中的其他部分
 }
 
 impl HTMLPost {
-    pub fn new(name: String, content: String) -> Self {
-        HTMLPost { name, content }
+    pub fn new(name: String, content: String, body: String) -> Self {
+        HTMLPost {
+            name,
+            content,
+            body,
+        }
+    }
+
+    /// 文章正文的HTML，其中的地址相对于articles目录
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// 用f改写页面内容，例如离线模式下替换外部资源
+    pub fn rewrite(self, f: impl FnOnce(&str) -> Result<String>) -> Result<Self> {
+        Ok(HTMLPost {
+            content: f(&self.content)?,
+            ..self
+        })
     }
 
-    pub fn write_into_folder(self, path: impl AsRef<Path>) {
-        let path = path.as_ref().display();
-        let path = format!("{path}{}.html", self.name);
-        fs::write(path, self.content).unwrap_or_else(|_| panic!("[错误]该路径父文件夹不存在"))
+    /// 写入到path文件夹下，返回写入的文件路径
+    pub fn write_into_folder(self, path: impl AsRef<Path>) -> Result<PathBuf> {
+        let path = path.as_ref().join(format!("{}.html", self.name));
+        fs::write(&path, self.content).map_err(|e| PackpalError::io(&path, e))?;
+        Ok(path)
     }
 }
 
 /// 文章元数据，将会用于产生index page
-#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+///
+/// 可以写在posts/metadata.json里（以文件名为键），也可以写在文章开头的front matter里。
+/// 两处都有的字段以front matter为准，都没有的字段使用默认值（例如标题默认是文件名）。
+#[derive(Clone, Default, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[serde(default)]
 pub struct PostMetadata {
-    #[serde(rename = "create_at")]
-    pub(crate) date: String, // 博客日期
-    #[serde(rename = "hashtags")]
-    pub(crate) tags: Vec<String>, // hashtags
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) title: Option<String>, // 文章标题
+    #[serde(rename = "create_at", alias = "date")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) date: Option<String>, // 博客日期
+    #[serde(rename = "hashtags", alias = "tags")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) tags: Option<Vec<String>>, // hashtags
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) description: Option<String>, // 文章简介
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) draft: Option<bool>, // 是否是草稿
 }
 
 impl PostMetadata {
-    pub fn new(date: String, tags: Vec<String>, url: String) -> Self {
-        Self { date, tags }
+    /// 合并两份元数据，self中已有的字段优先，缺少的字段从fallback中补上
+    pub fn or(self, fallback: &PostMetadata) -> PostMetadata {
+        PostMetadata {
+            title: self.title.or_else(|| fallback.title.clone()),
+            date: self.date.or_else(|| fallback.date.clone()),
+            tags: self.tags.or_else(|| fallback.tags.clone()),
+            description: self.description.or_else(|| fallback.description.clone()),
+            draft: self.draft.or(fallback.draft),
+        }
     }
 }
 
-#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[derive(Clone, Default, Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct PostMetadataList(HashMap<String, PostMetadata>);
 
 impl PostMetadataList {
@@ -54,16 +104,17 @@ impl PostMetadataList {
         self.0.get(post_name)
     }
 
-    pub fn from_json(path: impl AsRef<Path>) -> Self {
-        let json =
-            fs::read_to_string(path).unwrap_or_else(|_| panic!("[错误]该路径父文件夹不存在"));
-        serde_json::from_str(&json).unwrap_or_else(|_| panic!("[错误]该json文件格式错误"))
+    pub fn from_json(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path).map_err(|e| PackpalError::io(path, e))?;
+        serde_json::from_str(&json).map_err(|e| PackpalError::metadata(path, e))
     }
 
-    pub fn write_into(self, path: impl AsRef<Path>) {
-        let json =
-            serde_json::to_string(&self).unwrap_or_else(|_| panic!("[错误]该json文件格式错误"));
-        fs::write(path, json).unwrap_or_else(|_| panic!("[错误]该路径不存在"))
+    #[cfg(test)]
+    pub fn write_into(self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_string(&self).map_err(|e| PackpalError::metadata(path, e))?;
+        fs::write(path, json).map_err(|e| PackpalError::io(path, e))
     }
 }
 
@@ -73,9 +124,8 @@ pub struct RawPost {
     pub(crate) name: String,      // 文件名，也是博客的标题
     source_type: SourceType,      // 文件类型，现在只支持 markdown 文件
     path: PathBuf,                // 文件路径（相对路径）
-    content: Option<String>,      // 文件内容，懒加载，只有在hydrate的时候才load进内存
-    tags: Option<Vec<String>>,    // 文章的tags
-    date: Option<String>,         // 文章日期 例如 2024.01.01
+    content: Option<String>, // 文件内容（不含front matter），懒加载，只有在hydrate的时候才load进内存
+    metadata: PostMetadata,  // 文章元数据，front matter和metadata.json合并后的结果
     reading_time: Option<String>, // 预计阅读需要的时间 例如 5分钟
 }
 
@@ -86,57 +136,107 @@ impl RawPost {
             source_type,
             path,
             content: None,
-            tags: None,
-            date: None,
+            metadata: PostMetadata::default(),
             reading_time: None,
         }
     }
 
-
-
-    pub fn render(mut self, template: &PostTemplate) -> (PostInfo, HTMLPost) {
+    /// 读取源文件，解析其中的front matter
+    pub fn load(&mut self) -> Result<()> {
         if self.content.is_none() {
-            self.load_content_from_path()
+            self.load_content_from_path()?
         }
+        Ok(())
+    }
+
+    pub fn render(
+        mut self,
+        template: &PostTemplate,
+        options: &MarkdownOptions,
+        date_format: &str,
+        timezone: Tz,
+    ) -> Result<(PostInfo, HTMLPost, Vec<Asset>)> {
+        self.load()?;
 
+        let post_info = self.info(options, date_format, timezone);
         let binding = self.content.take().unwrap();
-        let parser = Parser::new_ext(&binding, Options::all());
-
-        let mut content = String::new();
-        html::push_html(&mut content, parser);
-
-        let post_content = template
-            .0
-            .replace(
-                "<PostDate/>",
-                &self.date.clone().unwrap_or("----.--.--".to_string()),
-            )
-            .replace(
-                "<ReadingTime/>",
-                &self.reading_time.clone().unwrap_or("<1分钟".to_string()),
-            )
-            .replace("<PostTags/>", &self.get_tags())
-            .replace(
-                "<PostHeading/>",
-                &format!("<h1 class=\"post-title\">{}</h1>", self.name.clone()),
-            )
-            .replace("<ContentRoot/>", &content);
-
-        let post_info = PostInfo {
-            title: self.name.clone(),
-            date: self.date.take().unwrap_or("----.--.--".to_string()),
-            url: format!("articles/{}", self.name),
-            excerpt: self.content.clone().unwrap_or("".to_string()).drain(..100).collect(),
+        let rendered = match self.source_type {
+            SourceType::Markdown => markdown::render(&binding, options),
+        };
+        let (content, assets) = link_assets(&rendered.html, &self.path)?;
+
+        let output = PathBuf::from(format!("articles/{}.html", self.name));
+        let ctx = context! {
+            post => context! {
+                reading_time => self.reading_time.take().unwrap_or("<1分钟".to_string()),
+                toc => rendered.toc,
+                ..Value::from_serialize(&post_info)
+            },
+            content => Value::from_safe_string(content.clone()),
+            root => "../",
+            highlight_css => options
+                .highlight
+                .uses_stylesheet()
+                .then(|| format!("../public/{HIGHLIGHT_CSS}")),
+            mathjax => rendered.math,
+            prism => rendered.prism,
         };
+        let post_content = template.0.render(POST_TEMPLATE, ctx, output)?;
 
-        (post_info, HTMLPost::new(self.name, post_content))
+        Ok((
+            post_info,
+            HTMLPost::new(self.name, post_content, content),
+            assets,
+        ))
     }
 
-    fn load_content_from_path(&mut self) {
-        self.content = Some(
-            fs::read_to_string(&self.path).unwrap_or_else(|_| panic!("[错误]找不到博客源文件")),
-        );
-        self.estimate_reading_time()
+    /// 文章在主页等列表页面中展示的信息，日期按date_format格式化，没有时区的日期按timezone计算，需要先load
+    ///
+    /// 元数据中有description时用它作为摘要，否则从正文中提取
+    pub fn info(&self, options: &MarkdownOptions, date_format: &str, timezone: Tz) -> PostInfo {
+        let content = self.content.as_deref().unwrap_or_default();
+        let datetime = self.date(timezone).ok().flatten();
+        PostInfo {
+            title: self.title(),
+            date: datetime
+                .map_or_else(|| MISSING_DATE.to_string(), |date| date.format(date_format)),
+            datetime,
+            url: format!("articles/{}", self.name),
+            excerpt: match &self.metadata.description {
+                Some(description) => description.clone(),
+                None => markdown::excerpt(content, &options.excerpt),
+            },
+            description: self.metadata.description.clone(),
+            tags: self.metadata.tags.clone().unwrap_or_default(),
+            draft: self.is_draft(),
+        }
+    }
+
+    /// 文章输入的哈希值，包括文件名、元数据、正文、模板和渲染选项，需要先load
+    pub fn fingerprint(&self, template: &PostTemplate, options: &MarkdownOptions) -> String {
+        let metadata = serde_json::to_string(&self.metadata).unwrap_or_default();
+        let options = serde_json::to_string(options).unwrap_or_default();
+        ContentHash::new()
+            .update(template.0.fingerprint())
+            .update(options)
+            .update(&self.name)
+            .update(metadata)
+            .update(self.content.as_deref().unwrap_or_default())
+            .finish()
+    }
+
+    fn load_content_from_path(&mut self) -> Result<()> {
+        let content = fs::read_to_string(&self.path).map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidData => {
+                PackpalError::render(&self.path, "博客源文件不是有效的UTF-8编码")
+            }
+            _ => PackpalError::io(&self.path, e),
+        })?;
+        let (front_matter, body) = split_front_matter(&self.path, &content)?;
+        self.metadata = front_matter.or(&self.metadata);
+        self.content = Some(body.to_string());
+        self.estimate_reading_time();
+        Ok(())
     }
 
     // 估计阅读时间，统计中文字符，然后除以“250字/分钟”，得到估计时间
@@ -153,19 +253,38 @@ impl RawPost {
         }
     }
 
-    pub fn set_tag(&mut self, tags: Vec<String>) {
-        self.tags = Some(tags)
+    /// 用metadata.json中的元数据补全front matter中没有的字段
+    pub fn merge_metadata(&mut self, metadata: &PostMetadata) {
+        self.metadata = self.metadata.clone().or(metadata)
+    }
+
+    /// 文章标题，没有在元数据中指定时使用文件名
+    pub fn title(&self) -> String {
+        self.metadata
+            .title
+            .clone()
+            .unwrap_or_else(|| self.name.clone())
     }
 
-    pub fn set_date(&mut self, date: String) {
-        self.date = Some(date)
+    /// 元数据中是不是标记了`draft: true`
+    pub fn is_draft(&self) -> bool {
+        self.metadata.draft.unwrap_or(false)
     }
 
-    fn get_tags(&self) -> String {
-        if let Some(tags) = &self.tags {
-            tags.join("·")
-        } else {
-            String::from("无标签")
+    /// 文章的发表时间，没有日期时返回None，日期无法识别时报错，没有写时区时按timezone计算
+    pub fn date(&self, timezone: Tz) -> Result<Option<PostDate>> {
+        match self.metadata.date.as_deref().map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(date) if date == MISSING_DATE => Ok(None),
+            Some(date) => PostDate::parse(date, timezone).map(Some).ok_or_else(|| {
+                PackpalError::metadata(
+                    &self.path,
+                    format!(
+                        "文章《{}》的日期“{date}”无法识别，可以写成“2024.09.07”、“2024-09-07 20:30”或者“2024-09-07T20:30:00+08:00”",
+                        self.name
+                    ),
+                )
+            }),
         }
     }
 }
@@ -178,21 +297,22 @@ fn is_chinese_character(c: char) -> bool {
     false
 }
 
-/// 博客模板
+/// 文章模板，对应templates目录下的posts_template.html
 #[derive(Clone)]
-pub struct PostTemplate(String);
+pub struct PostTemplate(TemplateEngine);
 
 impl PostTemplate {
-    pub fn imports(path: impl AsRef<Path>) -> Self {
-        let template =
-            fs::read_to_string(path).unwrap_or_else(|_| panic!("[错误]找不到posts_template.html"));
-        PostTemplate(template)
+    pub fn imports(engine: &TemplateEngine) -> Result<Self> {
+        // 模板里没有用到content（旧版模板中的<ContentRoot/>）时，文章内容就没有地方放
+        engine.check(POST_TEMPLATE, &["content"])?;
+        Ok(PostTemplate(engine.clone()))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::functions::TEST_ROOT;
 
     // 测试读写
     #[test]
@@ -201,17 +321,45 @@ mod tests {
         metadata.insert(
             "我的第一篇博客".to_string(),
             PostMetadata {
-                date: "2024.09.07".to_string(),
-                tags: vec!["博客".to_string(), "站点".to_string()],
+                date: Some("2024.09.07".to_string()),
+                tags: Some(vec!["博客".to_string(), "站点".to_string()]),
+                ..PostMetadata::default()
             },
         );
 
         let metadata = PostMetadataList(metadata);
 
-        metadata.clone().write_into("./src/templates/metadata.json");
+        let path = format!("{TEST_ROOT}test_post_metadata/metadata.json");
+        fs::create_dir_all(format!("{TEST_ROOT}test_post_metadata")).unwrap();
+        metadata.clone().write_into(&path).unwrap();
 
-        let metadata2 = PostMetadataList::from_json("./src/templates/metadata.json");
+        let metadata2 = PostMetadataList::from_json(&path).unwrap();
 
         assert_eq!(metadata, metadata2)
     }
+
+    #[test]
+    fn test_front_matter_overrides_metadata_json() {
+        let dir = format!("{TEST_ROOT}test_front_matter");
+        fs::create_dir_all(&dir).unwrap();
+        let path = PathBuf::from(format!("{dir}/比特币.md"));
+        fs::write(
+            &path,
+            "---\ntitle: 比特币入门\ntags: [比特币]\n---\n# 比特币\n",
+        )
+        .unwrap();
+
+        let mut post = RawPost::new("比特币".to_string(), SourceType::Markdown, path);
+        post.load().unwrap();
+        post.merge_metadata(&PostMetadata {
+            date: Some("2024.09.07".to_string()),
+            tags: Some(vec!["博客".to_string()]),
+            ..PostMetadata::default()
+        });
+
+        assert_eq!(post.title(), "比特币入门");
+        assert_eq!(post.metadata.date.as_deref(), Some("2024.09.07"));
+        assert_eq!(post.metadata.tags, Some(vec!["比特币".to_string()]));
+        assert_eq!(post.content.as_deref(), Some("# 比特币\n"));
+    }
 }
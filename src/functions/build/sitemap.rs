@@ -0,0 +1,166 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::dates::PostDate;
+use crate::functions::build::feed::absolute_url;
+use crate::functions::build::highlight::escape_html;
+use crate::functions::build::index::PostInfo;
+use crate::functions::build::manifest::BuildManifest;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// 站点地图的文件名
+pub static SITEMAP_FILE: &str = "sitemap.xml";
+/// 爬虫规则的文件名
+pub static ROBOTS_FILE: &str = "robots.txt";
+
+/// 站点地图和robots.txt的选项，对应config.json中的sitemap
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct SitemapOptions {
+    pub enabled: bool,         // 是否生成sitemap.xml，需要设置site_url
+    pub robots: bool,          // 是否生成引用站点地图的robots.txt
+    pub disallow: Vec<String>, // robots.txt中禁止爬虫访问的路径，例如"/public/"
+}
+
+impl Default for SitemapOptions {
+    fn default() -> Self {
+        SitemapOptions {
+            enabled: true,
+            robots: true,
+            disallow: Vec::new(),
+        }
+    }
+}
+
+/// 站点地图，列出网站中所有页面的地址和最后修改日期
+#[derive(Debug, Clone)]
+pub struct Sitemap {
+    site_url: String,
+    pages: Vec<(String, Option<String>)>, // 页面的完整地址和最后修改日期
+}
+
+impl Sitemap {
+    pub fn new(site_url: &str) -> Self {
+        Sitemap {
+            site_url: site_url.to_string(),
+            pages: Vec::new(),
+        }
+    }
+
+    /// 加入一个页面，path相对于网站根目录，dates是页面中文章的发表时间，最新的一个作为最后修改日期
+    pub fn add(&mut self, path: &str, dates: impl IntoIterator<Item = Option<PostDate>>) {
+        let lastmod = dates
+            .into_iter()
+            .flatten()
+            .max()
+            .map(|date| date.format("%Y-%m-%d").to_string());
+        self.pages
+            .push((absolute_url(&self.site_url, path), lastmod));
+    }
+
+    /// 加入一个列出文章的页面（主页、标签页、归档页），草稿不算在最后修改日期里
+    pub fn add_listing(&mut self, path: &str, posts: &[PostInfo]) {
+        self.add(path, published(posts).map(|post| post.datetime));
+    }
+
+    /// 和add_listing一样，但是只列出了草稿的页面（例如只有草稿用到的标签）不加入站点地图
+    pub fn add_published_listing(&mut self, path: &str, posts: &[PostInfo]) {
+        if published(posts).next().is_some() {
+            self.add_listing(path, posts);
+        }
+    }
+
+    pub fn xml(&self) -> String {
+        let mut urls = String::new();
+        for (loc, lastmod) in &self.pages {
+            urls.push_str(&format!("  <url>\n    <loc>{}</loc>\n", escape_html(loc)));
+            if let Some(lastmod) = lastmod {
+                urls.push_str(&format!("    <lastmod>{lastmod}</lastmod>\n"));
+            }
+            urls.push_str("  </url>\n");
+        }
+        format!(
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+                "{}",
+                "</urlset>\n"
+            ),
+            urls
+        )
+    }
+
+    pub fn robots(&self, options: &SitemapOptions) -> String {
+        let mut robots = String::from("User-agent: *\n");
+        if options.disallow.is_empty() {
+            robots.push_str("Allow: /\n");
+        }
+        for path in &options.disallow {
+            robots.push_str(&format!("Disallow: {path}\n"));
+        }
+        format!(
+            "{robots}\nSitemap: {}\n",
+            absolute_url(&self.site_url, SITEMAP_FILE)
+        )
+    }
+
+    /// 生成sitemap.xml和robots.txt，内容没有变化时不重写文件
+    pub fn write_into(
+        &self,
+        out_dir: impl AsRef<Path>,
+        options: &SitemapOptions,
+        manifest: &mut BuildManifest,
+    ) -> Result<()> {
+        let out_dir = out_dir.as_ref();
+        let mut files = vec![(SITEMAP_FILE, self.xml())];
+        if options.robots {
+            files.push((ROBOTS_FILE, self.robots(options)));
+        }
+        for (file, content) in files {
+            let path = out_dir.join(file);
+            if fs::read_to_string(&path).ok() != Some(content.clone()) {
+                fs::write(&path, content).map_err(|e| PackpalError::io(&path, e))?;
+            }
+            manifest.record(out_dir, path);
+        }
+        Ok(())
+    }
+}
+
+// 已经发表的文章，草稿即使用--drafts生成了也不能出现在站点地图中
+fn published(posts: &[PostInfo]) -> impl Iterator<Item = &PostInfo> {
+    posts.iter().filter(|post| !post.draft)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Tz;
+
+    #[test]
+    fn test_sitemap() {
+        let mut sitemap = Sitemap::new("https://example.com/blog/");
+        let date = |date| PostDate::parse(date, Tz::UTC);
+        sitemap.add("", [date("2023.01.02"), date("2024-09-07"), None]);
+        sitemap.add("articles/比特币 & 以太坊", [date("2023.01.02")]);
+        sitemap.add("articles/没有日期", [None]);
+
+        let xml = sitemap.xml();
+        assert!(
+            xml.contains("<loc>https://example.com/blog/</loc>\n    <lastmod>2024-09-07</lastmod>")
+        );
+        assert!(
+            xml.contains("<loc>https://example.com/blog/articles/比特币%20&amp;%20以太坊</loc>")
+        );
+        assert!(xml.contains("<loc>https://example.com/blog/articles/没有日期</loc>\n  </url>"));
+
+        let options = SitemapOptions {
+            disallow: vec![String::from("/public/")],
+            ..SitemapOptions::default()
+        };
+        assert_eq!(
+            sitemap.robots(&options),
+            "User-agent: *\nDisallow: /public/\n\nSitemap: https://example.com/blog/sitemap.xml\n"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use crate::error::Result;
+use crate::functions::build::cache::ContentHash;
+use crate::functions::build::index::PostInfo;
+use crate::functions::build::template::TemplateEngine;
+use minijinja::context;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// 标签页模板的文件名
+pub static TAG_TEMPLATE: &str = "tag_template.html";
+/// 标签页在输出目录中的位置
+pub static TAGS_OUTPUT: &str = "tags";
+
+/// 一个标签，以及所有带有这个标签的文章
+#[derive(Debug, Clone, Serialize)]
+pub struct Tag {
+    pub name: String,
+    pub url: String, // 标签页相对于网站根目录的地址，例如`tags/区块链/`
+    pub count: usize,
+    #[serde(skip)]
+    pub posts: Vec<PostInfo>,
+}
+
+/// 标签在地址中的名字：转成小写，空白和在路径、地址中有特殊含义的字符换成`-`，中文等字符保持原样
+pub fn tag_slug(tag: &str) -> String {
+    let mut slug = String::new();
+    for c in tag.trim().chars() {
+        if c.is_whitespace() || "/\\?#%&\"'<>|:*.".contains(c) {
+            if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        } else {
+            slug.extend(c.to_lowercase());
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        String::from("tag")
+    } else {
+        slug.to_string()
+    }
+}
+
+/// 标签页相对于网站根目录的地址，模板中的`{{ tag | tag_url }}`
+pub fn tag_url(tag: &str) -> String {
+    format!("{TAGS_OUTPUT}/{}/", tag_slug(tag))
+}
+
+/// 按标签把文章分组，文章多的标签排在前面
+///
+/// 只有大小写或者符号不同的标签（例如`Rust`和`rust`）会生成同一个标签页，使用第一次出现时的写法。
+pub fn collect_tags(posts: &[PostInfo]) -> Vec<Tag> {
+    let mut tags: BTreeMap<String, Tag> = BTreeMap::new();
+    for post in posts {
+        for name in &post.tags {
+            let url = tag_url(name);
+            let tag = tags.entry(url.clone()).or_insert_with(|| Tag {
+                name: name.clone(),
+                url,
+                count: 0,
+                posts: Vec::new(),
+            });
+            // 同一篇文章里重复的标签只算一次
+            if !tag.posts.iter().any(|tagged| tagged.url == post.url) {
+                tag.count += 1;
+                tag.posts.push(post.clone());
+            }
+        }
+    }
+    let mut tags = tags.into_values().collect::<Vec<_>>();
+    tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    tags
+}
+
+/// 标签页模板，对应templates目录下的tag_template.html
+///
+/// 同一个模板既用来生成每个标签的文章列表（`tags/<标签>/`），也用来生成所有标签的总览（`tags/`）。
+#[derive(Clone)]
+pub struct TagTemplate(TemplateEngine);
+
+impl TagTemplate {
+    pub fn imports(engine: &TemplateEngine) -> Result<Self> {
+        engine.check(TAG_TEMPLATE, &[])?;
+        Ok(TagTemplate(engine.clone()))
+    }
+
+    /// 标签页输入的哈希值，包括模板、所有标签和这个标签的文章，tag为None时是总览页
+    pub fn fingerprint(&self, site_name: &str, tags: &[Tag], tag: Option<&Tag>) -> String {
+        // Tag序列化时不包含文章，标签页中列出的文章要单独计算
+        let posts = tag.map(|tag| tag.posts.as_slice()).unwrap_or_default();
+        ContentHash::new()
+            .update(self.0.fingerprint())
+            .update(site_name)
+            .update(serde_json::to_string(tags).unwrap_or_default())
+            .update(tag.map(|tag| tag.url.as_str()).unwrap_or_default())
+            .update(serde_json::to_string(&posts).unwrap_or_default())
+            .finish()
+    }
+
+    /// 渲染标签页，tag为None时渲染总览页，root是页面到网站根目录的相对路径
+    pub fn render(
+        &self,
+        site_name: &str,
+        tags: &[Tag],
+        tag: Option<&Tag>,
+        root: &str,
+        output: impl AsRef<Path>,
+    ) -> Result<String> {
+        let ctx = context! {
+            site_name,
+            root,
+            tags,
+            tag,
+            posts => tag.map(|tag| tag.posts.as_slice()).unwrap_or_default(),
+        };
+        self.0.render(TAG_TEMPLATE, ctx, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_tags() {
+        let post = |title: &str, tags: &[&str]| PostInfo {
+            title: title.to_string(),
+            date: String::from("2024.09.07"),
+            datetime: None,
+            url: format!("articles/{title}"),
+            excerpt: String::new(),
+            description: None,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            draft: false,
+        };
+        let posts = [
+            post("比特币", &["区块链", "Rust"]),
+            post("以太坊", &["区块链", "智能 合约", "区块链"]),
+            post("所有权", &["rust", "C++/C#"]),
+        ];
+        let tags = collect_tags(&posts);
+        let summary = tags
+            .iter()
+            .map(|tag| (tag.name.as_str(), tag.url.as_str(), tag.count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("Rust", "tags/rust/", 2),
+                ("区块链", "tags/区块链/", 2),
+                ("C++/C#", "tags/c++-c/", 1),
+                ("智能 合约", "tags/智能-合约/", 1),
+            ]
+        );
+        assert_eq!(tag_slug("../"), "tag");
+    }
+}
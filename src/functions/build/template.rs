@@ -0,0 +1,224 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::cache::hash_dir;
+use crate::functions::build::dates::PostDate;
+use crate::functions::build::tags::tag_url;
+use chrono_tz::Tz;
+use minijinja::{Environment, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// 默认的局部模板，项目的templates目录里没有同名文件时使用
+static POST_CARD: &str = include_str!("../../templates/partials/post_card.html");
+static POST_LIST_ITEM: &str = include_str!("../../templates/partials/post_list_item.html");
+static POST_TAGS: &str = include_str!("../../templates/partials/post_tags.html");
+static POST_TOC: &str = include_str!("../../templates/partials/post_toc.html");
+static TAG_PAGE: &str = include_str!("../../templates/tag_template.html");
+static ARCHIVE_PAGE: &str = include_str!("../../templates/archive_template.html");
+
+/// 内置的局部模板：(模板名, 内容)，packpal new 会把它们写进新项目的templates目录
+pub static DEFAULT_PARTIALS: [(&str, &str); 4] = [
+    ("partials/post_card.html", POST_CARD),
+    ("partials/post_list_item.html", POST_LIST_ITEM),
+    ("partials/post_tags.html", POST_TAGS),
+    ("partials/post_toc.html", POST_TOC),
+];
+
+/// 内置的页面模板：(模板名, 内容)，旧项目的templates目录里没有这些模板时使用内置的版本
+pub static DEFAULT_PAGES: [(&str, &str); 2] = [
+    ("tag_template.html", TAG_PAGE),
+    ("archive_template.html", ARCHIVE_PAGE),
+];
+
+// 旧版模板中的特殊标记，加载模板时替换成对应的模板语法
+static LEGACY_MARKERS: [(&str, &str); 6] = [
+    ("<ContentRoot/>", "{{ content }}"),
+    ("<PostDate/>", "{{ post.date }}"),
+    ("<ReadingTime/>", "{{ post.reading_time }}"),
+    ("<PostTags/>", "{% include \"partials/post_tags.html\" %}"),
+    ("<PostToc/>", "{% include \"partials/post_toc.html\" %}"),
+    (
+        "<PostHeading/>",
+        "<h1 class=\"post-title\">{{ post.title }}</h1>",
+    ),
+];
+
+/// 模板引擎，从项目的templates目录加载模板
+///
+/// 模板使用Jinja语法（变量、for循环、if条件、过滤器、include局部模板），
+/// 同时兼容旧版模板中的`<ContentRoot/>`等标记。
+#[derive(Clone)]
+pub struct TemplateEngine {
+    env: Arc<Environment<'static>>,
+    template_dir: PathBuf,
+    fingerprint: String, // 模板目录中所有文件的哈希值
+}
+
+impl TemplateEngine {
+    /// 从template_dir加载模板，date过滤器中没有时区的日期按timezone计算
+    pub fn load(template_dir: impl AsRef<Path>, timezone: Tz) -> Result<Self> {
+        let template_dir = template_dir.as_ref().to_path_buf();
+        if !template_dir.is_dir() {
+            return Err(PackpalError::io(
+                &template_dir,
+                io::Error::new(io::ErrorKind::NotFound, "模板目录不存在"),
+            ));
+        }
+
+        let mut env = Environment::new();
+        let dir = template_dir.clone();
+        env.set_loader(move |name| load_template(&dir, name));
+        env.add_filter("date", move |value, format| {
+            date_filter(value, format, timezone)
+        });
+        env.add_filter("tag_url", |tag: String| tag_url(&tag));
+        Ok(TemplateEngine {
+            env: Arc::new(env),
+            fingerprint: hash_dir(&template_dir)?,
+            template_dir,
+        })
+    }
+
+    /// 模板目录的哈希值，任何一个模板变化都会改变它
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    /// 检查模板是否存在、语法是否正确，并且用到了required中的所有变量
+    pub fn check(&self, name: &str, required: &[&str]) -> Result<()> {
+        let variables = self.variables(name)?;
+        for variable in required {
+            if !variables.contains(*variable) {
+                return Err(PackpalError::template(
+                    self.template_dir.join(name),
+                    format!("模板中没有用到{variable}"),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// 模板中用到的、需要从外部传入的变量
+    pub fn variables(&self, name: &str) -> Result<HashSet<String>> {
+        let template = self
+            .env
+            .get_template(name)
+            .map_err(|e| self.template_error(name, e))?;
+        Ok(template.undeclared_variables(false))
+    }
+
+    /// 用ctx渲染名为name的模板，output是渲染结果对应的文件，用于报错
+    pub fn render(&self, name: &str, ctx: Value, output: impl AsRef<Path>) -> Result<String> {
+        let template = self
+            .env
+            .get_template(name)
+            .map_err(|e| self.template_error(name, e))?;
+        template
+            .render(ctx)
+            .map_err(|e| PackpalError::render(output, format!("使用模板{name}时出错：{e:#}")))
+    }
+
+    fn template_error(&self, name: &str, err: minijinja::Error) -> PackpalError {
+        let path = self.template_dir.join(err.name().unwrap_or(name));
+        PackpalError::template(path, format!("{err:#}"))
+    }
+}
+
+// 优先读取项目中的模板，找不到时使用内置的局部模板和页面模板
+fn load_template(dir: &Path, name: &str) -> std::result::Result<Option<String>, minijinja::Error> {
+    let path = dir.join(name);
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            match DEFAULT_PARTIALS
+                .iter()
+                .chain(DEFAULT_PAGES.iter())
+                .find(|(partial, _)| *partial == name)
+            {
+                Some((_, source)) => source.to_string(),
+                None => return Ok(None),
+            }
+        }
+        Err(e) => {
+            return Err(minijinja::Error::new(
+                minijinja::ErrorKind::InvalidOperation,
+                format!("读取模板{}失败", path.display()),
+            )
+            .with_source(e));
+        }
+    };
+    Ok(Some(upgrade_legacy_markers(source)))
+}
+
+/// 把旧版模板中的`<ContentRoot/>`等标记替换成模板语法
+pub fn upgrade_legacy_markers(source: String) -> String {
+    LEGACY_MARKERS
+        .iter()
+        .fold(source, |source, (marker, replacement)| {
+            source.replace(marker, replacement)
+        })
+}
+
+// 日期过滤器：{{ post.datetime | date("%Y年%m月%d日") }}，无法识别的日期原样输出，没有日期时输出空字符串
+fn date_filter(value: Value, format: String, timezone: Tz) -> String {
+    if value.is_none() || value.is_undefined() {
+        return String::new();
+    }
+    let value = value.to_string();
+    PostDate::parse(&value, timezone)
+        .map(|date| date.format(&format))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::TEST_ROOT;
+    use minijinja::context;
+
+    #[test]
+    fn test_template_engine() {
+        let dir = format!("{TEST_ROOT}test_template_engine");
+        fs::create_dir_all(format!("{dir}/partials")).unwrap();
+        fs::write(
+            format!("{dir}/list.html"),
+            "{% for post in posts %}{% include \"partials/item.html\" %}{% endfor %}\
+             {% if not posts %}空{% endif %}",
+        )
+        .unwrap();
+        fs::write(
+            format!("{dir}/partials/item.html"),
+            "<li>{{ post.title }} {{ post.date | date(\"%Y年%m月%d日\") }}</li>",
+        )
+        .unwrap();
+        fs::write(format!("{dir}/legacy.html"), "<PostHeading/><ContentRoot/>").unwrap();
+
+        let engine = TemplateEngine::load(&dir, Tz::UTC).unwrap();
+        let posts = vec![context! { title => "<比特币>", date => "2024.09.07" }];
+        let html = engine
+            .render("list.html", context! { posts }, "list.html")
+            .unwrap();
+        assert_eq!(html, "<li>&lt;比特币&gt; 2024年09月07日</li>");
+        let empty: Vec<Value> = Vec::new();
+        let html = engine
+            .render("list.html", context! { posts => empty }, "list.html")
+            .unwrap();
+        assert_eq!(html, "空");
+
+        engine.check("legacy.html", &["content", "post"]).unwrap();
+        let ctx = context! {
+            post => context! { title => "比特币" },
+            content => Value::from_safe_string("<p>正文</p>".to_string()),
+        };
+        let html = engine.render("legacy.html", ctx, "legacy.html").unwrap();
+        assert_eq!(html, "<h1 class=\"post-title\">比特币</h1><p>正文</p>");
+
+        assert!(matches!(
+            engine.check("list.html", &["content"]),
+            Err(PackpalError::Template { .. })
+        ));
+        assert!(engine.check("missing.html", &[]).is_err());
+    }
+}
@@ -1 +1,151 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::manifest::{BuildManifest, MANIFEST_FILE, resolve_entry};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+/// 删除out_dir下由packpal生成的文件
+///
+/// 默认只删除构建清单中记录的文件，以及因此变空的文件夹，其他文件都不会被碰到。
+/// all为true时清空整个out_dir，这时必须同时传入confirmed确认。
+pub fn clean(out_dir: impl AsRef<Path>, all: bool, confirmed: bool) -> Result<()> {
+    let out_dir = out_dir.as_ref();
+    if !out_dir.exists() {
+        println!("[信息]{}不存在，没有需要清理的文件", out_dir.display());
+        return Ok(());
+    }
+    if all {
+        return clean_all(out_dir, confirmed);
+    }
+
+    let Some(manifest) = BuildManifest::load(out_dir)? else {
+        println!(
+            "[信息]{}下没有找到构建清单{MANIFEST_FILE}，没有需要清理的文件",
+            out_dir.display()
+        );
+        return Ok(());
+    };
+    let removed = remove_generated(out_dir, manifest.files())?;
+    let manifest_path = out_dir.join(MANIFEST_FILE);
+    fs::remove_file(&manifest_path).map_err(|e| PackpalError::io(&manifest_path, e))?;
+
+    let remaining = fs::read_dir(out_dir)
+        .map_err(|e| PackpalError::io(out_dir, e))?
+        .count();
+    println!("[信息]已删除{removed}个生成的文件");
+    if remaining > 0 {
+        println!(
+            "[信息]{}下还有{remaining}项不是由packpal生成的内容，已保留",
+            out_dir.display()
+        );
+    }
+    Ok(())
+}
+
+// 清空整个输出目录，但保留目录本身
+fn clean_all(out_dir: &Path, confirmed: bool) -> Result<()> {
+    if !confirmed {
+        return Err(PackpalError::usage(
+            out_dir,
+            "--all会删除这个目录下的所有内容，确认要清空时请运行packpal clean --all --yes",
+        ));
+    }
+    // 防止把项目目录（或者它的上级目录）当成输出目录清空
+    let current_dir = std::env::current_dir()
+        .and_then(|dir| dir.canonicalize())
+        .map_err(|e| PackpalError::io(".", e))?;
+    let canonical_out_dir = out_dir
+        .canonicalize()
+        .map_err(|e| PackpalError::io(out_dir, e))?;
+    if current_dir.starts_with(canonical_out_dir) {
+        return Err(PackpalError::usage(
+            out_dir,
+            "当前目录在这个目录里面，拒绝清空；请先切换到这个目录之外再运行，或者用--out指定其他输出目录",
+        ));
+    }
+
+    for entry in fs::read_dir(out_dir).map_err(|e| PackpalError::io(out_dir, e))? {
+        let path = entry.map_err(|e| PackpalError::io(out_dir, e))?.path();
+        let removed = if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        removed.map_err(|e| PackpalError::io(&path, e))?;
+    }
+    println!("[信息]已清空{}", out_dir.display());
+    Ok(())
+}
+
+/// 删除out_dir下清单中记录的文件，并删除因此变空的文件夹，返回删除的文件数
+///
+/// 不合法的记录（绝对路径、包含`..`）和已经不存在的文件会被跳过
+pub(crate) fn remove_generated<'a>(
+    out_dir: &Path,
+    files: impl IntoIterator<Item = &'a str>,
+) -> Result<usize> {
+    let mut removed = 0;
+    let mut parents = BTreeSet::new();
+    for file in files {
+        let Some(path) = resolve_entry(out_dir, file) else {
+            println!("[警告]构建清单中的路径{file}不在输出目录内，已跳过");
+            continue;
+        };
+        if !path.is_file() {
+            continue;
+        }
+        fs::remove_file(&path).map_err(|e| PackpalError::io(&path, e))?;
+        removed += 1;
+
+        let mut parent = path.parent().map(Path::to_path_buf);
+        while let Some(dir) = parent.filter(|dir| dir != out_dir && dir.starts_with(out_dir)) {
+            parent = dir.parent().map(Path::to_path_buf);
+            parents.insert(dir);
+        }
+    }
+
+    // 从最深的文件夹开始删除，非空的文件夹会删除失败，直接忽略
+    let mut parents: Vec<PathBuf> = parents.into_iter().collect();
+    parents.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    for dir in parents {
+        let _ = fs::remove_dir(dir);
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::TEST_ROOT;
+
+    #[test]
+    fn test_clean_only_generated_files() {
+        let out_dir = PathBuf::from(format!("{TEST_ROOT}test_clean/build"));
+        let _ = fs::remove_dir_all(&out_dir);
+        fs::create_dir_all(out_dir.join("articles")).unwrap();
+        fs::create_dir_all(out_dir.join("public")).unwrap();
+        fs::write(out_dir.join("index.html"), "").unwrap();
+        fs::write(out_dir.join("articles/比特币.html"), "").unwrap();
+        fs::write(out_dir.join("public/avatar.png"), "").unwrap();
+        fs::write(out_dir.join("public/CNAME"), "").unwrap();
+
+        let mut manifest = BuildManifest::default();
+        manifest.record(&out_dir, out_dir.join("index.html"));
+        manifest.record(&out_dir, out_dir.join("articles/比特币.html"));
+        manifest.record(&out_dir, out_dir.join("public/avatar.png"));
+        manifest.save(&out_dir).unwrap();
+
+        clean(&out_dir, false, false).unwrap();
+        assert!(!out_dir.join("index.html").exists());
+        assert!(!out_dir.join("articles").exists());
+        assert!(!out_dir.join(MANIFEST_FILE).exists());
+        assert!(out_dir.join("public/CNAME").exists());
+
+        let err = clean(&out_dir, true, false).unwrap_err();
+        assert!(matches!(err, PackpalError::Usage { .. }));
+        assert!(err.to_string().contains("--yes"));
+        clean(&out_dir, true, true).unwrap();
+        assert!(out_dir.exists());
+        assert_eq!(fs::read_dir(&out_dir).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,271 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::dates::{
+    DEFAULT_DATE_FORMAT, DEFAULT_TIMEZONE, parse_timezone, validate_format,
+};
+use crate::functions::build::feed::FeedOptions;
+use crate::functions::build::images::ImageOptions;
+use crate::functions::build::index::IndexOptions;
+use crate::functions::build::markdown::MarkdownOptions;
+use crate::functions::build::offline::OfflineConfig;
+use crate::functions::build::sitemap::SitemapOptions;
+use crate::functions::deploy::DeployConfig;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::{env, fs};
+
+/// 配置文件的文件名，也是用来识别项目根目录的标志
+pub static CONFIG_FILE: &str = "config.json";
+
+// 内置的默认配置，config.json中没有写posts_dir等目录时使用其中的值
+static DEFAULT_CONFIG: &str = include_str!("../templates/default_config.json");
+static DEFAULT_DIRS: LazyLock<DefaultDirs> = LazyLock::new(|| {
+    serde_json::from_str(DEFAULT_CONFIG).expect("内置的default_config.json格式错误")
+});
+
+// default_config.json中的目录设置，其他字段不需要
+#[derive(Debug, Deserialize)]
+struct DefaultDirs {
+    posts_dir: String,
+    templates_dir: String,
+    build_dir: String,
+}
+
+/// 站点配置，对应项目根目录下的 config.json
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SiteConfig {
+    pub blog_name: String,
+    #[serde(default)]
+    pub site_url: String, // 网站的完整地址，例如https://example.com/blog/，订阅源和站点地图中的链接需要完整地址
+    #[serde(rename = "avatar")]
+    pub avatar_path: String,
+    #[serde(default)]
+    pub motto: String,
+    #[serde(default)]
+    pub contacts: Contacts,
+    #[serde(default)]
+    pub deploy: DeployConfig,
+    #[serde(default)]
+    pub markdown: MarkdownOptions, // 文章渲染选项，例如目录包含哪些级别的标题
+    #[serde(default)]
+    pub images: ImageOptions, // 图片处理选项，例如生成哪些宽度的图片
+    #[serde(default)]
+    pub feed: FeedOptions, // 订阅源选项
+    #[serde(default)]
+    pub sitemap: SitemapOptions, // 站点地图和robots.txt选项
+    #[serde(default = "default_date_format")]
+    pub date_format: String, // 模板中文章日期的格式，例如"%Y年%m月%d日"
+    #[serde(default = "default_timezone")]
+    pub timezone: String, // 文章日期没有写时区时使用的时区，例如"Asia/Shanghai"
+    #[serde(default)]
+    pub index: IndexOptions, // 主页选项，例如每一页展示多少篇文章
+    #[serde(default)]
+    pub offline: OfflineConfig, // 离线模式，把外部资源换成本地副本
+    #[serde(default = "default_posts_dir")]
+    pub posts_dir: String, // Markdown源文件目录，相对于项目根目录
+    #[serde(default = "default_templates_dir")]
+    pub templates_dir: String, // 模板目录，相对于项目根目录
+    #[serde(default = "default_build_dir")]
+    pub build_dir: String, // 输出目录，相对于项目根目录
+}
+
+fn default_date_format() -> String {
+    DEFAULT_DATE_FORMAT.to_string()
+}
+
+fn default_timezone() -> String {
+    DEFAULT_TIMEZONE.to_string()
+}
+
+fn default_posts_dir() -> String {
+    DEFAULT_DIRS.posts_dir.clone()
+}
+
+fn default_templates_dir() -> String {
+    DEFAULT_DIRS.templates_dir.clone()
+}
+
+fn default_build_dir() -> String {
+    DEFAULT_DIRS.build_dir.clone()
+}
+
+/// 联系方式，没有填写的项为空字符串
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Contacts {
+    pub github: String,
+    pub wechat: String,
+    pub phone: String,
+    pub qq: String,
+    pub email: String,
+}
+
+impl SiteConfig {
+    pub fn from_json(path: impl AsRef<Path>) -> Result<SiteConfig> {
+        let path = path.as_ref();
+        let config = fs::read_to_string(path).map_err(|e| PackpalError::io(path, e))?;
+        let config: SiteConfig =
+            serde_json::from_str(&config).map_err(|e| PackpalError::config(path, e))?;
+        config.markdown.validate(path)?;
+        config.images.validate(path)?;
+        validate_format(&config.date_format, path)?;
+        parse_timezone(&config.timezone, path)?;
+        let site_url = config.site_url.to_ascii_lowercase();
+        if !site_url.is_empty()
+            && !["http://", "https://"]
+                .iter()
+                .any(|s| site_url.starts_with(s))
+        {
+            return Err(PackpalError::config(
+                path,
+                format!(
+                    "site_url应该是以http://或https://开头的完整地址，现在是{}",
+                    config.site_url
+                ),
+            ));
+        }
+        Ok(config)
+    }
+
+    /// 文章日期没有写时区时使用的时区，from_json中已经检查过，无效时按UTC计算
+    pub fn timezone(&self) -> Tz {
+        self.timezone.trim().parse().unwrap_or(Tz::UTC)
+    }
+
+    /// 配置文件中的相对路径（例如头像）都是相对于配置文件所在目录的
+    pub fn resolve(config_path: impl AsRef<Path>, relative: impl AsRef<Path>) -> PathBuf {
+        match config_path.as_ref().parent() {
+            Some(root) => root.join(relative),
+            None => relative.as_ref().to_path_buf(),
+        }
+    }
+}
+
+/// 命令行中指定的路径，相对于当前目录，会覆盖配置文件中的设置
+#[derive(Debug, Clone, Default)]
+pub struct PathOverrides {
+    pub config: Option<PathBuf>,
+    pub posts: Option<PathBuf>,
+    pub templates: Option<PathBuf>,
+    pub out: Option<PathBuf>,
+}
+
+/// 一个博客项目用到的所有路径
+#[derive(Debug, Clone)]
+pub struct ProjectPaths {
+    pub config: PathBuf,
+    pub posts: PathBuf,
+    pub templates: PathBuf,
+    pub out: PathBuf,
+}
+
+impl ProjectPaths {
+    /// 从当前目录开始查找项目，读取配置文件并确定各个目录的位置
+    pub fn discover(overrides: &PathOverrides) -> Result<(ProjectPaths, SiteConfig)> {
+        let current_dir = env::current_dir().map_err(|e| PackpalError::io(".", e))?;
+        Self::discover_from(current_dir, overrides)
+    }
+
+    /// 从start目录开始查找项目
+    ///
+    /// 没有指定--config时，从start开始逐级向上查找config.json，找到的目录就是项目根目录。
+    /// 命令行中的路径优先，其次是配置文件中的posts_dir、templates_dir、build_dir。
+    pub fn discover_from(
+        start: impl AsRef<Path>,
+        overrides: &PathOverrides,
+    ) -> Result<(ProjectPaths, SiteConfig)> {
+        let config_path = match &overrides.config {
+            Some(config) => config.clone(),
+            None => find_project_root(&start)
+                .map(|root| root.join(CONFIG_FILE))
+                .ok_or_else(|| {
+                    PackpalError::config(
+                        start.as_ref().join(CONFIG_FILE),
+                        "在当前目录及其上级目录中都找不到配置文件，请在项目目录下运行，或者使用packpal new创建新项目",
+                    )
+                })?,
+        };
+        let config = SiteConfig::from_json(&config_path)?;
+        let root = match config_path.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => PathBuf::new(),
+        };
+
+        let paths = ProjectPaths {
+            posts: overrides
+                .posts
+                .clone()
+                .unwrap_or_else(|| join_relative(&root, &config.posts_dir)),
+            templates: overrides
+                .templates
+                .clone()
+                .unwrap_or_else(|| join_relative(&root, &config.templates_dir)),
+            out: overrides
+                .out
+                .clone()
+                .unwrap_or_else(|| join_relative(&root, &config.build_dir)),
+            config: config_path,
+        };
+        Ok((paths, config))
+    }
+}
+
+// 拼接root和配置文件中的相对路径，去掉开头多余的"./"
+fn join_relative(root: &Path, relative: &str) -> PathBuf {
+    let relative = Path::new(relative);
+    root.join(relative.strip_prefix(".").unwrap_or(relative))
+}
+
+/// 从start开始逐级向上查找包含config.json的目录
+pub fn find_project_root(start: impl AsRef<Path>) -> Option<PathBuf> {
+    start
+        .as_ref()
+        .ancestors()
+        .find(|dir| dir.join(CONFIG_FILE).is_file())
+        .map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::TEST_ROOT;
+    use crate::functions::new::new_project;
+
+    #[test]
+    fn test_default_dirs() {
+        assert_eq!(default_posts_dir(), "./posts/");
+        assert_eq!(default_templates_dir(), "./templates/");
+        assert_eq!(default_build_dir(), "./build/");
+
+        // packpal new写出的config.json和内置的默认值保持一致
+        let config: serde_json::Value =
+            serde_json::from_str(include_str!("../templates/config.json")).unwrap();
+        assert_eq!(config["posts_dir"], default_posts_dir());
+        assert_eq!(config["templates_dir"], default_templates_dir());
+        assert_eq!(config["build_dir"], default_build_dir());
+    }
+
+    #[test]
+    fn test_discover_project_root() {
+        let root = format!("{TEST_ROOT}test_discover");
+        new_project(&root).unwrap();
+        let nested = PathBuf::from(&root).join("posts").join("drafts");
+        fs::create_dir_all(&nested).unwrap();
+
+        let (paths, config) =
+            ProjectPaths::discover_from(&nested, &PathOverrides::default()).unwrap();
+        assert_eq!(paths.config, PathBuf::from(&root).join(CONFIG_FILE));
+        assert_eq!(config.timezone(), Tz::UTC);
+        assert_eq!(paths.posts, PathBuf::from(&root).join("./posts/"));
+        assert_eq!(paths.out, PathBuf::from(&root).join("./build/"));
+
+        let overrides = PathOverrides {
+            out: Some(PathBuf::from("elsewhere")),
+            ..PathOverrides::default()
+        };
+        let (paths, _) = ProjectPaths::discover_from(&nested, &overrides).unwrap();
+        assert_eq!(paths.out, PathBuf::from("elsewhere"));
+        assert_eq!(paths.templates, PathBuf::from(&root).join("./templates/"));
+    }
+}
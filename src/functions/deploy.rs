@@ -1 +1,276 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::cache::CACHE_FILE;
+use crate::functions::build::manifest::MANIFEST_FILE;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs, process};
 
+/// 部署配置，对应 config.json 中的 "deploy" 字段
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeployConfig {
+    pub remote: String,       // 远程仓库地址，任何git支持的URL都可以，包括本地的裸仓库
+    pub branch: String,       // 推送到的分支，默认是 gh-pages
+    pub message: String,      // 提交信息，其中的 {date} 会被替换成部署时间
+    pub name: Option<String>, // 提交者名字，为空时使用git的全局配置
+    pub email: Option<String>, // 提交者邮箱，为空时使用git的全局配置
+}
+
+impl Default for DeployConfig {
+    fn default() -> Self {
+        Self {
+            remote: String::new(),
+            branch: String::from("gh-pages"),
+            message: String::from("Site updated: {date}"),
+            name: None,
+            email: None,
+        }
+    }
+}
+
+impl DeployConfig {
+    fn commit_message(&self) -> String {
+        let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+        self.message.replace("{date}", &now.to_string())
+    }
+}
+
+// 当git没有配置提交者信息时使用的默认身份
+static DEFAULT_NAME: &str = "PackPal";
+static DEFAULT_EMAIL: &str = "packpal@localhost";
+
+/// 把build_dir下的所有文件作为一次提交推送到远程仓库的指定分支
+///
+/// 部署时会在临时目录下创建一个独立的git仓库，以build_dir作为工作区：
+/// 如果远程分支已经存在，新的提交会接在远程分支的最新提交之后；
+/// 否则创建一个孤立（orphan）分支。部署完成后临时仓库会被删除。
+pub fn deploy(build_dir: impl AsRef<Path>, config: &DeployConfig) -> Result<()> {
+    if config.remote.is_empty() {
+        return Err(PackpalError::deploy(
+            "",
+            "没有配置远程仓库地址，请在config.json的deploy.remote中填写，或者使用--remote参数",
+        ));
+    }
+    let build_dir = build_dir.as_ref();
+    let mut entries = fs::read_dir(build_dir).map_err(|e| PackpalError::io(build_dir, e))?;
+    if entries.next().is_none() {
+        return Err(PackpalError::deploy(
+            &config.remote,
+            format!(
+                "{}下没有可以部署的文件，请先运行packpal build",
+                build_dir.display()
+            ),
+        ));
+    }
+
+    let repo = DeployRepo::init(build_dir, config)?;
+    let branch_ref = format!("refs/heads/{}", config.branch);
+    repo.git(["symbolic-ref", "HEAD", &branch_ref])?;
+
+    // ls-remote --exit-code 在找不到对应分支时返回2，其他非零返回值都是真正的错误
+    let remote_branch = repo.run(["ls-remote", "--exit-code", &config.remote, &branch_ref])?;
+    let branch_exists = match remote_branch.status.code() {
+        Some(0) => true,
+        Some(2) => false,
+        _ => return Err(repo.error("ls-remote", &remote_branch)),
+    };
+    if branch_exists {
+        repo.git([
+            "fetch",
+            "--quiet",
+            "--depth",
+            "1",
+            &config.remote,
+            &branch_ref,
+        ])?;
+        repo.git(["reset", "--quiet", "--mixed", "FETCH_HEAD"])?;
+    }
+
+    // 构建清单和构建缓存只是给packpal自己用的，不需要发布出去
+    let exclude_manifest = format!(":(exclude){MANIFEST_FILE}");
+    let exclude_cache = format!(":(exclude){CACHE_FILE}");
+    repo.git(["add", "--all", "--", ".", &exclude_manifest, &exclude_cache])?;
+    if branch_exists && repo.run(["diff", "--cached", "--quiet"])?.status.success() {
+        println!("[信息]构建内容没有变化，跳过部署");
+        return Ok(());
+    }
+    repo.git(["commit", "--quiet", "-m", &config.commit_message()])?;
+    repo.git([
+        "push",
+        "--quiet",
+        &config.remote,
+        &format!("HEAD:{branch_ref}"),
+    ])?;
+
+    println!("[信息]已部署到 {} 的 {} 分支", config.remote, config.branch);
+    Ok(())
+}
+
+/// 部署用的临时git仓库，drop的时候删除
+struct DeployRepo {
+    remote: String,
+    git_dir: PathBuf,
+    work_tree: PathBuf,
+    identity: Vec<(&'static str, String)>,
+}
+
+impl DeployRepo {
+    fn init(work_tree: &Path, config: &DeployConfig) -> Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let mut repo = DeployRepo {
+            remote: config.remote.clone(),
+            git_dir: env::temp_dir().join(format!("packpal-deploy-{}-{nanos}", process::id())),
+            work_tree: work_tree
+                .canonicalize()
+                .map_err(|e| PackpalError::io(work_tree, e))?,
+            identity: Vec::new(),
+        };
+        repo.git(["init", "--quiet"])?;
+
+        let name = config
+            .name
+            .clone()
+            .or_else(|| repo.config_value("user.name"));
+        let email = config
+            .email
+            .clone()
+            .or_else(|| repo.config_value("user.email"));
+        let name = name.unwrap_or_else(|| DEFAULT_NAME.to_string());
+        let email = email.unwrap_or_else(|| DEFAULT_EMAIL.to_string());
+        repo.identity = vec![
+            ("GIT_AUTHOR_NAME", name.clone()),
+            ("GIT_COMMITTER_NAME", name),
+            ("GIT_AUTHOR_EMAIL", email.clone()),
+            ("GIT_COMMITTER_EMAIL", email),
+        ];
+        Ok(repo)
+    }
+
+    fn config_value(&self, key: &str) -> Option<String> {
+        let output = self.run(["config", "--get", key]).ok()?;
+        let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.status.success() && !value.is_empty()).then_some(value)
+    }
+
+    // 执行git命令，返回原始输出，不检查退出码
+    fn run<I, S>(&self, args: I) -> Result<Output>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Command::new("git")
+            .arg("--git-dir")
+            .arg(&self.git_dir)
+            .arg("--work-tree")
+            .arg(&self.work_tree)
+            .args(args)
+            .envs(self.identity.iter().map(|(k, v)| (k, v)))
+            .output()
+            .map_err(|e| {
+                PackpalError::deploy(&self.remote, format!("无法执行git，请确认已经安装git：{e}"))
+            })
+    }
+
+    // 执行git命令，退出码非零时返回错误
+    fn git<'a, I>(&self, args: I) -> Result<Output>
+    where
+        I: IntoIterator<Item = &'a str> + Clone,
+    {
+        let output = self.run(args.clone())?;
+        if output.status.success() {
+            Ok(output)
+        } else {
+            let command = args.into_iter().collect::<Vec<_>>().join(" ");
+            Err(self.error(&command, &output))
+        }
+    }
+
+    fn error(&self, command: &str, output: &Output) -> PackpalError {
+        PackpalError::deploy(
+            &self.remote,
+            format!(
+                "执行 git {command} 失败：{}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        )
+    }
+}
+
+impl Drop for DeployRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.git_dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::TEST_ROOT;
+
+    fn remote_git(remote: &str, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(["-c", "core.quotePath=false", "--git-dir"])
+            .arg(remote)
+            .args(args)
+            .output()
+            .expect("执行git失败");
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    #[test]
+    fn test_deploy_to_bare_repo() {
+        let root = format!("{TEST_ROOT}test_deploy");
+        let _ = fs::remove_dir_all(&root);
+        let build_dir = format!("{root}/build");
+        let remote = format!("{root}/remote.git");
+        fs::create_dir_all(format!("{build_dir}/articles")).unwrap();
+        fs::write(format!("{build_dir}/index.html"), "<h1>hello</h1>").unwrap();
+        fs::write(format!("{build_dir}/articles/比特币.html"), "<p>比特币</p>").unwrap();
+        fs::write(format!("{build_dir}/{MANIFEST_FILE}"), "{}").unwrap();
+        fs::write(format!("{build_dir}/{CACHE_FILE}"), "{}").unwrap();
+        let status = Command::new("git")
+            .args(["init", "--quiet", "--bare", &remote])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let config = DeployConfig {
+            remote: remote.clone(),
+            message: String::from("deploy at {date}"),
+            ..DeployConfig::default()
+        };
+        deploy(&build_dir, &config).unwrap();
+
+        let files = remote_git(&remote, &["ls-tree", "-r", "--name-only", "gh-pages"]);
+        assert!(files.lines().any(|f| f == "index.html"));
+        assert!(files.lines().any(|f| f == "articles/比特币.html"));
+        assert!(!files.lines().any(|f| f == MANIFEST_FILE || f == CACHE_FILE));
+        let message = remote_git(&remote, &["log", "-1", "--format=%s", "gh-pages"]);
+        assert!(message.starts_with("deploy at ") && !message.contains("{date}"));
+
+        // 内容没有变化时不产生新的提交，有变化时接在已有的提交之后
+        deploy(&build_dir, &config).unwrap();
+        assert_eq!(
+            remote_git(&remote, &["rev-list", "--count", "gh-pages"]),
+            "1"
+        );
+        fs::write(format!("{build_dir}/index.html"), "<h1>updated</h1>").unwrap();
+        deploy(&build_dir, &config).unwrap();
+        assert_eq!(
+            remote_git(&remote, &["rev-list", "--count", "gh-pages"]),
+            "2"
+        );
+    }
+
+    #[test]
+    fn test_deploy_without_remote() {
+        let err = deploy("./src/", &DeployConfig::default()).unwrap_err();
+        assert!(matches!(err, PackpalError::Deploy { .. }));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::{DEFAULT_PAGES, DEFAULT_PARTIALS};
 use std::fs;
 
 // 博客模板文件
@@ -16,49 +18,68 @@ static CONFIG_TEMPLATE: &str = include_str!("../templates/config.json");
  * │   ├── metadata.json            (存放文章的元数据，如标题、日期等)
  * │   └── 我的第一篇博客.md           (示例博客文章)
  * ├── templates/                   (模板HTML存放地方)
+ * │   ├── partials/                (局部模板，可以在其他模板中include)
  * │   ├── posts_template.html      (文章模板文件)
- * │   └── index_template.html      (主页模板文件)
+ * │   ├── index_template.html      (主页模板文件)
+ * │   ├── tag_template.html        (标签页模板文件)
+ * │   └── archive_template.html    (归档页模板文件)
  * ├── build/                       (用来存放打包合成后的文件)
  * ├── avatar.png                   (博客头像)
  * ├── config.json                  (站点元数据)
  * └── README.md                    (PackPal使用指南)
  */
-pub fn new_project(name: impl ToString) {
+pub fn new_project(name: impl ToString) -> Result<()> {
     // 创建项目目录
     let project_dir = format!("{}/", name.to_string());
-    fs::create_dir_all(&project_dir).expect("[错误]创建项目目录失败");
+    fs::create_dir_all(&project_dir).map_err(|e| PackpalError::io(&project_dir, e))?;
     // 创建posts目录
     let posts_dir = format!("{}/posts", project_dir);
-    fs::create_dir_all(&posts_dir).expect("[错误]创建posts目录失败");
+    fs::create_dir_all(&posts_dir).map_err(|e| PackpalError::io(&posts_dir, e))?;
     // 创建templates目录
     let templates_dir = format!("{}/templates", project_dir);
-    fs::create_dir_all(&templates_dir).expect("[错误]创建templates目录失败");
+    fs::create_dir_all(&templates_dir).map_err(|e| PackpalError::io(&templates_dir, e))?;
     // 创建build目录
     let build_dir = format!("{}/build", project_dir);
-    fs::create_dir_all(&build_dir).expect("[错误]创建build目录失败");
+    fs::create_dir_all(&build_dir).map_err(|e| PackpalError::io(&build_dir, e))?;
     // 创建README.md文件
     let readme_path = format!("{}/README.md", project_dir);
-    fs::write(&readme_path, README).expect("[错误]创建README.md文件失败");
+    fs::write(&readme_path, README).map_err(|e| PackpalError::io(&readme_path, e))?;
     // 创建avatar.png
     let avatar_path = format!("{}/avatar.png", project_dir);
-    fs::write(&avatar_path, AVATAR).expect("[错误]创建avatar.png文件失败");
+    fs::write(&avatar_path, AVATAR).map_err(|e| PackpalError::io(&avatar_path, e))?;
     // 创建config.json
     let config_path = format!("{}/config.json", project_dir);
-    fs::write(&config_path, CONFIG_TEMPLATE).expect("[错误]创建config.json失败");
+    fs::write(&config_path, CONFIG_TEMPLATE).map_err(|e| PackpalError::io(&config_path, e))?;
     // 创建posts_template.html文件
     let posts_template_path = format!("{}/posts_template.html", templates_dir);
-    fs::write(&posts_template_path, POSTS_TEMPLATE).expect("[错误]创建posts_template.html文件失败");
+    fs::write(&posts_template_path, POSTS_TEMPLATE)
+        .map_err(|e| PackpalError::io(&posts_template_path, e))?;
     // 创建index_template.html文件
     let index_template_path = format!("{}/index_template.html", templates_dir);
-    fs::write(&index_template_path, INDEX_TEMPLATE).expect("[错误]创建index_template.html文件失败");
+    fs::write(&index_template_path, INDEX_TEMPLATE)
+        .map_err(|e| PackpalError::io(&index_template_path, e))?;
+    // 创建标签页等其他页面的模板
+    for (name, page) in DEFAULT_PAGES {
+        let page_path = format!("{}/{}", templates_dir, name);
+        fs::write(&page_path, page).map_err(|e| PackpalError::io(&page_path, e))?;
+    }
+    // 创建局部模板
+    for (name, partial) in DEFAULT_PARTIALS {
+        let partial_path = format!("{}/{}", templates_dir, name);
+        let partial_dir = format!("{}/partials", templates_dir);
+        fs::create_dir_all(&partial_dir).map_err(|e| PackpalError::io(&partial_dir, e))?;
+        fs::write(&partial_path, partial).map_err(|e| PackpalError::io(&partial_path, e))?;
+    }
     // 创建metadata.json文件
     let metadata_path = format!("{}/metadata.json", posts_dir);
-    fs::write(&metadata_path, METADATA_TEMPLATE).expect("[错误]创建metadata.json文件失败");
+    fs::write(&metadata_path, METADATA_TEMPLATE)
+        .map_err(|e| PackpalError::io(&metadata_path, e))?;
     // 创建我的第一篇博客.md文件
     let post_path = format!("{}/我的第一篇博客.md", posts_dir);
     fs::write(&post_path, "# 我的第一篇博客\n这是我的第一篇博客")
-        .expect("[错误]创建我的第一篇博客.md文件失败");
+        .map_err(|e| PackpalError::io(&post_path, e))?;
     println!("[信息]项目{project_dir}初始化完成！");
+    Ok(())
 }
 
 #[cfg(test)]
@@ -67,7 +88,7 @@ mod tests {
 
     #[test]
     fn test_new_project() {
-        new_project("test/test_project");
+        new_project("test/test_project").unwrap();
         assert!(fs::metadata("test/test_project").is_ok());
         assert!(fs::metadata("test/test_project/posts").is_ok());
     }
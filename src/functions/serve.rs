@@ -0,0 +1,404 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::assets::percent_decode;
+use crate::functions::build::cache::ContentHash;
+use crate::functions::build::manifest::resolve_entry;
+use crate::functions::build::{BuildOptions, build_with};
+use crate::functions::config::{ProjectPaths, SiteConfig};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+use tiny_http::{Header, Request, Response, Server};
+
+/// 浏览器等待重新加载的地址，只有预览服务器提供，不会出现在生成的站点中
+static LIVE_RELOAD_PATH: &str = "/__packpal/livereload";
+
+// 预览服务器返回HTML页面时注入的脚本：不断请求LIVE_RELOAD_PATH，服务器在站点重新生成后才返回，
+// 返回的版本号和页面的版本号不同时刷新页面
+static LIVE_RELOAD_SCRIPT: &str = r#"<script>
+(function () {
+    var version = __VERSION__;
+    function poll() {
+        fetch("/__packpal/livereload?version=" + version)
+            .then(function (response) { return response.text(); })
+            .then(function (latest) {
+                if (Number(latest) !== version) {
+                    location.reload();
+                } else {
+                    poll();
+                }
+            })
+            .catch(function () { setTimeout(poll, 1000); });
+    }
+    poll();
+})();
+</script>
+"#;
+
+// 一次等待重新加载的请求最多挂起的时间，超时后浏览器会重新发起请求
+const POLL_TIMEOUT: Duration = Duration::from_secs(30);
+// 编辑器保存文件时往往会连续产生多个事件，等待这段时间把它们合并成一次构建
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// 生成站点，在本地启动HTTP服务器预览输出目录，并在源文件变化时重新生成、通知浏览器刷新
+///
+/// 重新生成时借助构建缓存，只会重新渲染输入有变化的页面
+///
+/// 刷新脚本只在预览服务器返回页面时注入，输出目录中的文件和使用同样的options运行`packpal build`生成的完全一样
+///
+/// 预览草稿或定时发布的文章时生成到单独的预览目录（见[`preview_dir`]），不会改动输出目录，
+/// 避免之后的`packpal deploy`把它们发布出去。
+pub fn serve(
+    mut paths: ProjectPaths,
+    config: &SiteConfig,
+    port: u16,
+    options: &BuildOptions,
+) -> Result<()> {
+    if options.drafts || options.future {
+        paths.out = preview_dir(&paths.out);
+        println!(
+            "[信息]包含草稿或定时发布的文章，预览生成到{}，不会改动输出目录",
+            paths.out.display()
+        );
+    }
+    rebuild(&paths, options)?;
+
+    let server = Server::http(("127.0.0.1", port))
+        .map_err(|e| PackpalError::serve(format!("无法监听端口{port}：{e}")))?;
+    let version = Arc::new(SiteVersion::default());
+    let out_dir = paths.out.clone();
+    let server_version = version.clone();
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let out_dir = out_dir.clone();
+            let version = server_version.clone();
+            // 等待重新加载的请求会挂起很久，每个请求单独用一个线程处理
+            thread::spawn(move || handle_request(request, &out_dir, &version));
+        }
+    });
+
+    println!("[信息] 预览地址：http://127.0.0.1:{port}/ ，按Ctrl+C退出");
+    watch(&paths, config, &version, options)
+}
+
+/// 预览草稿和定时发布的文章时使用的输出目录，在系统临时目录中，每个项目的输出目录对应一个
+///
+/// 同一个项目每次预览都使用同一个目录，可以沿用上一次预览的构建缓存。
+pub fn preview_dir(out_dir: &Path) -> PathBuf {
+    let out_dir = std::path::absolute(out_dir).unwrap_or_else(|_| out_dir.to_path_buf());
+    let hash = ContentHash::new()
+        .update(out_dir.to_string_lossy().as_bytes())
+        .finish();
+    env::temp_dir().join(format!("packpal-preview-{}", &hash[..12]))
+}
+
+fn rebuild(paths: &ProjectPaths, options: &BuildOptions) -> Result<()> {
+    build_with(
+        &paths.config,
+        &paths.posts,
+        &paths.templates,
+        &paths.out,
+        options,
+    )
+}
+
+/// 站点的版本号，每次重新生成成功后加一
+#[derive(Default)]
+struct SiteVersion {
+    version: Mutex<u64>,
+    changed: Condvar,
+}
+
+impl SiteVersion {
+    fn current(&self) -> u64 {
+        *self.version.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn bump(&self) {
+        *self.version.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+        self.changed.notify_all();
+    }
+
+    // 等到版本号不再是seen，或者超时，返回当前的版本号
+    fn wait_newer(&self, seen: u64, timeout: Duration) -> u64 {
+        let version = self.version.lock().unwrap_or_else(PoisonError::into_inner);
+        let (version, _) = self
+            .changed
+            .wait_timeout_while(version, timeout, |version| *version == seen)
+            .unwrap_or_else(PoisonError::into_inner);
+        *version
+    }
+}
+
+// 需要监听的文件和目录，都是规范化（canonicalize）之后的路径，方便和文件事件中的路径比较
+struct WatchTargets {
+    config: PathBuf,
+    avatar: PathBuf,
+    posts: PathBuf,
+    templates: PathBuf,
+    out: PathBuf,
+}
+
+impl WatchTargets {
+    fn new(paths: &ProjectPaths, config: &SiteConfig) -> Result<Self> {
+        let canonicalize =
+            |path: &Path| fs::canonicalize(path).map_err(|e| PackpalError::io(path, e));
+        Ok(WatchTargets {
+            config: canonicalize(&paths.config)?,
+            avatar: canonicalize(&SiteConfig::resolve(&paths.config, &config.avatar_path))?,
+            posts: canonicalize(&paths.posts)?,
+            templates: canonicalize(&paths.templates)?,
+            out: canonicalize(&paths.out)?,
+        })
+    }
+
+    // 监听配置文件和头像所在的目录而不是文件本身，因为很多编辑器保存时会先删除再重新创建文件
+    fn dirs(&self) -> Vec<(&Path, RecursiveMode)> {
+        let mut dirs = vec![
+            (self.posts.as_path(), RecursiveMode::Recursive),
+            (self.templates.as_path(), RecursiveMode::Recursive),
+        ];
+        for file in [&self.config, &self.avatar] {
+            if let Some(parent) = file.parent()
+                && !dirs.iter().any(|(dir, _)| *dir == parent)
+            {
+                dirs.push((parent, RecursiveMode::NonRecursive));
+            }
+        }
+        dirs
+    }
+
+    // 文件是不是站点的输入，编辑器的临时文件、输出目录中的文件等不需要重新生成站点
+    fn is_source(&self, path: &Path) -> bool {
+        if path.starts_with(&self.out) {
+            return false;
+        }
+        if path == self.config || path == self.avatar || path.starts_with(&self.templates) {
+            return true;
+        }
+        // 文章目录下除了文章和metadata.json，还有文章引用的图片、附件等文件
+        let temporary = path.file_name().is_none_or(|name| {
+            let name = name.to_string_lossy();
+            name.starts_with('.') || name.ends_with('~') || name.ends_with(".swp")
+        });
+        path.starts_with(&self.posts) && !temporary
+    }
+}
+
+// 监听源文件的变化，重新生成站点，直到进程被结束
+fn watch(
+    paths: &ProjectPaths,
+    config: &SiteConfig,
+    version: &SiteVersion,
+    options: &BuildOptions,
+) -> Result<()> {
+    let targets = WatchTargets::new(paths, config)?;
+    let (sender, receiver) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = notify::recommended_watcher(sender)
+        .map_err(|e| PackpalError::serve(format!("无法监听文件变化：{e}")))?;
+    for (dir, mode) in targets.dirs() {
+        watcher
+            .watch(dir, mode)
+            .map_err(|e| PackpalError::serve(format!("无法监听{}：{e}", dir.display())))?;
+    }
+
+    while let Ok(event) = receiver.recv() {
+        let mut events = vec![event];
+        while let Ok(event) = receiver.recv_timeout(DEBOUNCE) {
+            events.push(event);
+        }
+
+        let changed = events
+            .into_iter()
+            .flatten()
+            .filter(|event| {
+                matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                )
+            })
+            .flat_map(|event| event.paths)
+            .any(|path| targets.is_source(&path));
+        if !changed {
+            continue;
+        }
+
+        println!("[信息] 检测到文件变化，重新生成站点");
+        let start = Instant::now();
+        match rebuild(paths, options) {
+            Ok(()) => {
+                version.bump();
+                println!("[信息] 生成完成，用时{}毫秒", start.elapsed().as_millis());
+            }
+            // 构建失败时保留上一次的结果，修改好源文件后会再次构建
+            Err(err) => eprintln!("{err}"),
+        }
+    }
+    Ok(())
+}
+
+fn handle_request(request: Request, out_dir: &Path, version: &SiteVersion) {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let response = if path == LIVE_RELOAD_PATH {
+        let seen = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("version="))
+            .and_then(|version| version.parse().ok())
+            .unwrap_or_else(|| version.current());
+        Response::from_string(version.wait_newer(seen, POLL_TIMEOUT).to_string())
+    } else {
+        serve_file(out_dir, &percent_decode(path), version.current())
+    };
+    // 浏览器可能已经关闭了页面，发送失败时直接忽略
+    let _ = request.respond(response);
+}
+
+fn serve_file(out_dir: &Path, path: &str, version: u64) -> Response<Cursor<Vec<u8>>> {
+    let Some(data) = locate(out_dir, path).and_then(|file| {
+        let data = fs::read(&file).ok()?;
+        Some((content_type(&file), data))
+    }) else {
+        return Response::from_string("404 Not Found").with_status_code(404);
+    };
+
+    let (content_type, data) = data;
+    let data = if content_type.starts_with("text/html") {
+        inject_live_reload(data, version)
+    } else {
+        data
+    };
+    let mut response = Response::from_data(data);
+    for (name, value) in [
+        ("Content-Type", content_type),
+        ("Cache-Control", "no-store"),
+    ] {
+        if let Ok(header) = Header::from_bytes(name, value) {
+            response.add_header(header);
+        }
+    }
+    response
+}
+
+// 把请求的路径转换成输出目录下的文件
+fn locate(out_dir: &Path, path: &str) -> Option<PathBuf> {
+    let relative = path.trim_matches('/');
+    let candidate = if relative.is_empty() {
+        out_dir.to_path_buf()
+    } else {
+        resolve_entry(out_dir, relative)?
+    };
+    if candidate.is_dir() {
+        let index = candidate.join("index.html");
+        return index.is_file().then_some(index);
+    }
+    if candidate.is_file() {
+        return Some(candidate);
+    }
+    // 主页中的文章链接不带.html后缀（articles/比特币），和GitHub Pages一样补上后缀再找
+    let file_name = candidate.file_name()?.to_string_lossy();
+    let html = candidate.with_file_name(format!("{file_name}.html"));
+    html.is_file().then_some(html)
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("txt" | "md") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("pdf") => "application/pdf",
+        Some("wasm") => "application/wasm",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("mp3") => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+// 在</body>之前插入刷新脚本，没有</body>时加在页面末尾
+fn inject_live_reload(html: Vec<u8>, version: u64) -> Vec<u8> {
+    let mut html = match String::from_utf8(html) {
+        Ok(html) => html,
+        Err(e) => return e.into_bytes(),
+    };
+    let script = LIVE_RELOAD_SCRIPT.replace("__VERSION__", &version.to_string());
+    let position = html.rfind("</body>").unwrap_or(html.len());
+    html.insert_str(position, &script);
+    html.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::TEST_ROOT;
+
+    #[test]
+    fn test_serve_files() {
+        let out_dir = PathBuf::from(format!("{TEST_ROOT}test_serve_files"));
+        fs::create_dir_all(out_dir.join("articles")).unwrap();
+        fs::write(out_dir.join("index.html"), "<html><body>主页</body></html>").unwrap();
+        fs::write(out_dir.join("articles/比特币.html"), "<p>比特币</p>").unwrap();
+
+        assert_eq!(locate(&out_dir, "/"), Some(out_dir.join("index.html")));
+        let path = percent_decode("/articles/%E6%AF%94%E7%89%B9%E5%B8%81");
+        assert_eq!(path, "/articles/比特币");
+        assert_eq!(
+            locate(&out_dir, &path),
+            Some(out_dir.join("articles/比特币.html"))
+        );
+        assert_eq!(locate(&out_dir, "/../config.json"), None);
+        assert_eq!(locate(&out_dir, "/missing.css"), None);
+
+        assert_eq!(content_type(Path::new("public/avatar.PNG")), "image/png");
+        let html = inject_live_reload("<body>主页</body>".as_bytes().to_vec(), 3);
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.starts_with("<body>主页<script>"));
+        assert!(html.contains("var version = 3;"));
+        assert!(html.ends_with("</script>\n</body>"));
+    }
+
+    #[test]
+    fn test_site_version_wakes_waiters() {
+        let version = Arc::new(SiteVersion::default());
+        let waiter = {
+            let version = version.clone();
+            thread::spawn(move || version.wait_newer(0, Duration::from_secs(10)))
+        };
+        thread::sleep(Duration::from_millis(50));
+        version.bump();
+        assert_eq!(waiter.join().unwrap(), 1);
+        // 没有新版本时等到超时，返回原来的版本号
+        assert_eq!(version.wait_newer(1, Duration::from_millis(10)), 1);
+    }
+
+    #[test]
+    fn test_preview_dir_is_outside_output() {
+        let out_dir = PathBuf::from(format!("{TEST_ROOT}test_preview_dir/build"));
+        let preview = preview_dir(&out_dir);
+        assert!(preview.starts_with(env::temp_dir()));
+        assert!(!preview.starts_with(&out_dir));
+        assert_eq!(preview, preview_dir(&out_dir));
+        assert_ne!(preview, preview_dir(Path::new("其他项目/build")));
+    }
+}
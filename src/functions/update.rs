@@ -0,0 +1,167 @@
+use crate::error::{PackpalError, Result};
+use crate::functions::build::build;
+use crate::functions::clean::clean;
+use crate::functions::deploy::{DeployConfig, deploy};
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// update 依次执行的步骤
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Step {
+    Build,
+    Clean,
+    Deploy,
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::Build => write!(f, "build"),
+            Step::Clean => write!(f, "clean"),
+            Step::Deploy => write!(f, "deploy"),
+        }
+    }
+}
+
+/// 先把站点构建到暂存目录，成功后清理旧的输出、换上新的输出，最后部署
+///
+/// 构建失败时暂存目录会被删除，out_dir里上一次的构建结果保持不变。
+/// 任何一步失败都会立即停止，并在错误信息中指明失败的步骤。
+pub fn update(
+    config_path: impl AsRef<Path>,
+    source_dir: impl AsRef<Path>,
+    template_dir: impl AsRef<Path>,
+    out_dir: impl AsRef<Path>,
+    deploy_config: &DeployConfig,
+) -> Result<()> {
+    let out_dir = out_dir.as_ref();
+    let staging_dir = staging_dir_of(out_dir);
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir)
+            .map_err(|e| step_error(Step::Build, PackpalError::io(&staging_dir, e)))?;
+    }
+
+    println!("[信息][1/3] 构建站点到暂存目录{}", staging_dir.display());
+    if let Err(err) = build(config_path, source_dir, template_dir, &staging_dir) {
+        let _ = fs::remove_dir_all(&staging_dir);
+        println!("[信息]构建失败，{}保持不变", out_dir.display());
+        return Err(step_error(Step::Build, err));
+    }
+
+    println!("[信息][2/3] 清理旧的构建结果并替换成新的");
+    fs::create_dir_all(out_dir)
+        .map_err(|e| PackpalError::io(out_dir, e))
+        .and_then(|_| clean(out_dir, false, false))
+        .and_then(|_| move_into(&staging_dir, out_dir))
+        .and_then(|_| {
+            fs::remove_dir_all(&staging_dir).map_err(|e| PackpalError::io(&staging_dir, e))
+        })
+        .map_err(|e| step_error(Step::Clean, e))?;
+
+    println!("[信息][3/3] 部署");
+    deploy(out_dir, deploy_config).map_err(|e| step_error(Step::Deploy, e))?;
+
+    println!("[信息]更新完成");
+    Ok(())
+}
+
+// 暂存目录和输出目录放在同一个文件夹下，保证可以直接rename
+fn staging_dir_of(out_dir: &Path) -> PathBuf {
+    let mut name = out_dir
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_else(|| OsString::from("build"));
+    name.push(".staging");
+    out_dir.with_file_name(name)
+}
+
+// 把from下的所有内容移动到to下，同名文件夹会合并，同名文件会被覆盖
+fn move_into(from: &Path, to: &Path) -> Result<()> {
+    for entry in fs::read_dir(from).map_err(|e| PackpalError::io(from, e))? {
+        let entry = entry.map_err(|e| PackpalError::io(from, e))?;
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() && target.is_dir() {
+            move_into(&entry.path(), &target)?;
+        } else {
+            fs::rename(entry.path(), &target).map_err(|e| PackpalError::io(&target, e))?;
+        }
+    }
+    Ok(())
+}
+
+fn step_error(step: Step, err: PackpalError) -> PackpalError {
+    PackpalError::Update {
+        step,
+        source: Box::new(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::new::new_project;
+    use crate::functions::{BLOG, TEST_ROOT};
+
+    #[test]
+    fn test_update_keeps_output_when_build_fails() {
+        let root = format!("{TEST_ROOT}test_update_rollback");
+        let _ = fs::remove_dir_all(&root);
+        new_project(&root).unwrap();
+        let out_dir = PathBuf::from(format!("{root}/build"));
+        fs::write(out_dir.join("index.html"), "旧的主页").unwrap();
+
+        // 模板目录不存在，构建一定会失败
+        let err = update(
+            format!("{root}/config.json"),
+            format!("{root}/posts/"),
+            format!("{root}/missing_templates/"),
+            &out_dir,
+            &DeployConfig::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            PackpalError::Update {
+                step: Step::Build,
+                ..
+            }
+        ));
+        assert_eq!(
+            fs::read_to_string(out_dir.join("index.html")).unwrap(),
+            "旧的主页"
+        );
+        assert!(!staging_dir_of(&out_dir).exists());
+    }
+
+    #[test]
+    fn test_update_reports_deploy_step() {
+        let root = format!("{TEST_ROOT}test_update_deploy");
+        let _ = fs::remove_dir_all(&root);
+        new_project(&root).unwrap();
+        fs::write(format!("{root}/posts/比特币.md"), BLOG).unwrap();
+        let out_dir = PathBuf::from(format!("{root}/build"));
+        fs::write(out_dir.join("CNAME"), "blog.example.com").unwrap();
+
+        // 没有配置远程仓库，构建和替换成功之后在部署这一步失败
+        let err = update(
+            format!("{root}/config.json"),
+            format!("{root}/posts/"),
+            format!("{root}/templates/"),
+            &out_dir,
+            &DeployConfig::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            PackpalError::Update {
+                step: Step::Deploy,
+                ..
+            }
+        ));
+        assert!(out_dir.join("articles/比特币.html").exists());
+        assert!(out_dir.join("CNAME").exists());
+        assert!(!staging_dir_of(&out_dir).exists());
+    }
+}
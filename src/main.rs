@@ -1,11 +1,15 @@
-use clap::{Parser as ClapParser, Subcommand};
-use std::error::Error;
-use crate::functions::build::build;
+use crate::error::Result;
+use crate::functions::build::{BuildOptions, build_with};
+use crate::functions::config::{PathOverrides, ProjectPaths};
+use clap::{Args, Parser as ClapParser, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
 /*
  * PackPal
  * 一个简单的静态博客生成器，使用Rust编写
  */
 
+mod error;
 mod functions;
 mod plugins;
 #[derive(ClapParser)]
@@ -13,31 +17,182 @@ mod plugins;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    #[command(flatten)]
+    paths: PathArgs,
+}
+
+// 项目路径相关的参数，所有子命令通用
+// 不指定--config时，从当前目录开始逐级向上查找config.json，找到的目录就是项目根目录
+#[derive(Args)]
+struct PathArgs {
+    /// 配置文件路径，默认从当前目录开始向上查找config.json
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Markdown源文件目录，默认使用config.json中的posts_dir
+    #[arg(long, global = true)]
+    posts: Option<PathBuf>,
+    /// 模板目录，默认使用config.json中的templates_dir
+    #[arg(long, global = true)]
+    templates: Option<PathBuf>,
+    /// 输出目录，默认使用config.json中的build_dir
+    #[arg(long, global = true)]
+    out: Option<PathBuf>,
+}
+
+impl From<PathArgs> for PathOverrides {
+    fn from(args: PathArgs) -> Self {
+        PathOverrides {
+            config: args.config,
+            posts: args.posts,
+            templates: args.templates,
+            out: args.out,
+        }
+    }
 }
 
 // packpal new [name]   在当前目录下创建名字为[name]的新项目
 // packpal build        生成静态站点文件，根据模板和markdown文件
-// packpal deploy       将生成的静态文件部署到Github pages
-// packpal update       就等于先build，再deploy
-// packpal clean        清楚生成的所有文件
+// packpal deploy       将生成的静态文件部署到Github pages（或任意git远程仓库）
+// packpal update       先build到暂存目录，成功后clean旧的输出并替换，再deploy
+// packpal clean        清除build生成的所有文件
+// packpal serve        生成站点并启动本地预览服务器，源文件变化时自动重新生成、刷新浏览器
 #[derive(Subcommand)]
 enum Commands {
-    New { project_name: String },
-    Build { output_dir: Option<String> },
-    Deploy,
+    New {
+        project_name: String,
+    },
+    Build {
+        /// 输出目录，和--out作用相同
+        output_dir: Option<PathBuf>,
+        /// 忽略构建缓存，重新生成所有页面
+        #[arg(long, short)]
+        force: bool,
+        /// 并行渲染文章的线程数，默认使用所有CPU核心
+        #[arg(long, short, value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: Option<u16>,
+        /// 离线模式：把外部资源换成vendor目录中的本地副本，缺少本地副本时报错
+        #[arg(long)]
+        offline: bool,
+        /// 生成标记为草稿（draft: true）的文章
+        #[arg(long)]
+        drafts: bool,
+        /// 生成发表时间还没到的文章
+        #[arg(long)]
+        future: bool,
+    },
+    Deploy {
+        /// 远程仓库地址，覆盖config.json中的deploy.remote
+        #[arg(long)]
+        remote: Option<String>,
+        /// 推送到的分支，覆盖config.json中的deploy.branch
+        #[arg(long)]
+        branch: Option<String>,
+        /// 提交信息，{date}会被替换成部署时间
+        #[arg(long, short)]
+        message: Option<String>,
+    },
     Update,
-    Clean,
+    Clean {
+        /// 清空整个输出目录，而不只是构建清单中记录的文件
+        #[arg(long)]
+        all: bool,
+        /// 确认使用--all清空输出目录
+        #[arg(long, short, requires = "all")]
+        yes: bool,
+    },
+    Serve {
+        /// 预览服务器监听的端口
+        #[arg(long, short, default_value_t = 4000)]
+        port: u16,
+        /// 预览标记为草稿（draft: true）的文章，页面中会标出“草稿”
+        #[arg(long)]
+        drafts: bool,
+        /// 预览发表时间还没到的文章
+        #[arg(long)]
+        future: bool,
+    },
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() -> ExitCode {
     let cli = Cli::parse();
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let mut overrides = PathOverrides::from(cli.paths);
 
     match cli.command {
-        Commands::New { project_name } => functions::new::new_project(project_name),
-        Commands::Build { .. } => {build("./posts/", "./templates/", "./build/")}
-        Commands::Deploy => {}
-        Commands::Update => {}
-        Commands::Clean => {}
+        Commands::New { project_name } => functions::new::new_project(project_name)?,
+        Commands::Build {
+            output_dir,
+            force,
+            jobs,
+            offline,
+            drafts,
+            future,
+        } => {
+            overrides.out = output_dir.or(overrides.out);
+            let (paths, _) = ProjectPaths::discover(&overrides)?;
+            let options = BuildOptions {
+                force,
+                jobs: jobs.map(usize::from),
+                offline,
+                drafts,
+                future,
+            };
+            build_with(
+                paths.config,
+                paths.posts,
+                paths.templates,
+                paths.out,
+                &options,
+            )?
+        }
+        Commands::Deploy {
+            remote,
+            branch,
+            message,
+        } => {
+            let (paths, config) = ProjectPaths::discover(&overrides)?;
+            let mut config = config.deploy;
+            config.remote = remote.unwrap_or(config.remote);
+            config.branch = branch.unwrap_or(config.branch);
+            config.message = message.unwrap_or(config.message);
+            functions::deploy::deploy(paths.out, &config)?
+        }
+        Commands::Update => {
+            let (paths, config) = ProjectPaths::discover(&overrides)?;
+            functions::update::update(
+                paths.config,
+                paths.posts,
+                paths.templates,
+                paths.out,
+                &config.deploy,
+            )?
+        }
+        Commands::Clean { all, yes } => {
+            let (paths, _) = ProjectPaths::discover(&overrides)?;
+            functions::clean::clean(paths.out, all, yes)?
+        }
+        Commands::Serve {
+            port,
+            drafts,
+            future,
+        } => {
+            let (paths, config) = ProjectPaths::discover(&overrides)?;
+            let options = BuildOptions {
+                drafts,
+                future,
+                ..BuildOptions::default()
+            };
+            functions::serve::serve(paths, &config, port, &options)?
+        }
     }
 
     Ok(())